@@ -0,0 +1 @@
+ALTER TABLE binance_klines DROP COLUMN derived;
@@ -0,0 +1 @@
+ALTER TABLE binance_klines ADD COLUMN derived BOOLEAN NOT NULL DEFAULT FALSE;
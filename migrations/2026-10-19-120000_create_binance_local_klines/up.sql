@@ -0,0 +1,45 @@
+-- Daily and weekly klines aggregated in a local timezone, sessions open
+-- `session_start` milliseconds after local midnight.
+CREATE TABLE binance_local_klines (
+  symbol VARCHAR(30) NOT NULL,
+  source market NOT NULL,
+  timezone VARCHAR(64) NOT NULL,
+  session_start BIGINT NOT NULL,
+  interval VARCHAR(10) NOT NULL,
+  local_date DATE NOT NULL,
+  open_time BIGINT NOT NULL,
+  close_time BIGINT NOT NULL,
+  open TEXT NOT NULL,
+  high TEXT NOT NULL,
+  low TEXT NOT NULL,
+  close TEXT NOT NULL,
+  base_volume TEXT NOT NULL,
+  quote_volume TEXT NOT NULL,
+  buy_base_volume TEXT NOT NULL,
+  buy_quote_volume TEXT NOT NULL,
+  number_of_trades BIGINT NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (symbol, source, timezone, session_start, interval, local_date)
+);
+
+SELECT diesel_manage_updated_at('binance_local_klines');
+
+CREATE VIEW binance_local_klines_view AS
+  SELECT symbol,
+         source,
+         timezone,
+         session_start * INTERVAL '1 millisecond' AS session_start,
+         interval,
+         local_date AS date,
+         TO_TIMESTAMP(open_time / 1000) AS open_time,
+         open::NUMERIC,
+         high::NUMERIC,
+         low::NUMERIC,
+         close::NUMERIC,
+         base_volume::NUMERIC AS volume,
+         COALESCE(buy_base_volume::NUMERIC / NULLIF(base_volume::NUMERIC, 0), 0) AS buy_percentage,
+         number_of_trades
+    FROM binance_local_klines
+   ORDER BY symbol ASC,
+            local_date ASC;
@@ -0,0 +1 @@
+DROP TABLE binance_agg_trades;
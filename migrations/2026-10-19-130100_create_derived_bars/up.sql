@@ -0,0 +1,26 @@
+-- Bars built from aggregated trades. `bar_type` is one of time, volume,
+-- dollar or tick and `size` the interval or threshold of the bar.
+CREATE TABLE derived_bars (
+  symbol VARCHAR(30) NOT NULL,
+  source market NOT NULL,
+  bar_type VARCHAR(10) NOT NULL,
+  size VARCHAR(40) NOT NULL,
+  first_agg_trade_id BIGINT NOT NULL,
+  last_agg_trade_id BIGINT NOT NULL,
+  open_time BIGINT NOT NULL,
+  close_time BIGINT NOT NULL,
+  open TEXT NOT NULL,
+  high TEXT NOT NULL,
+  low TEXT NOT NULL,
+  close TEXT NOT NULL,
+  base_volume TEXT NOT NULL,
+  quote_volume TEXT NOT NULL,
+  buy_base_volume TEXT NOT NULL,
+  buy_quote_volume TEXT NOT NULL,
+  number_of_trades BIGINT NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (symbol, source, bar_type, size, first_agg_trade_id)
+);
+
+SELECT diesel_manage_updated_at('derived_bars');
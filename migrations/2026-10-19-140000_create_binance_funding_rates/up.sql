@@ -0,0 +1,11 @@
+CREATE TABLE binance_funding_rates (
+  symbol VARCHAR(30) NOT NULL,
+  funding_time BIGINT NOT NULL,
+  funding_rate TEXT NOT NULL,
+  mark_price TEXT,
+  created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (symbol, funding_time)
+);
+
+SELECT diesel_manage_updated_at('binance_funding_rates');
@@ -0,0 +1 @@
+DROP TABLE binance_klines_quarantine;
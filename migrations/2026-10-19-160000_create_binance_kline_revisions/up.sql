@@ -0,0 +1,13 @@
+CREATE TABLE binance_kline_revisions (
+  id BIGSERIAL PRIMARY KEY,
+  symbol VARCHAR(30) NOT NULL,
+  open_time BIGINT NOT NULL,
+  close_time BIGINT NOT NULL,
+  source market NOT NULL,
+  old_values TEXT NOT NULL,
+  new_values TEXT NOT NULL,
+  run_id VARCHAR(32) NOT NULL,
+  changed_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX binance_kline_revisions_kline ON binance_kline_revisions (symbol, source, open_time);
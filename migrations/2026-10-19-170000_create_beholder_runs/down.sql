@@ -0,0 +1,10 @@
+DROP INDEX binance_funding_rates_run_id;
+ALTER TABLE binance_funding_rates DROP COLUMN run_id;
+
+DROP INDEX binance_open_interest_summaries_run_id;
+ALTER TABLE binance_open_interest_summaries DROP COLUMN run_id;
+
+DROP INDEX binance_klines_run_id;
+ALTER TABLE binance_klines DROP COLUMN run_id;
+
+DROP TABLE beholder_runs;
@@ -0,0 +1 @@
+DROP TABLE beholder_tasks;
@@ -0,0 +1,26 @@
+CREATE TABLE beholder_tasks (
+  id BIGSERIAL PRIMARY KEY,
+  job VARCHAR(64) NOT NULL,
+  dataset VARCHAR(20) NOT NULL,
+  source market NOT NULL,
+  symbol VARCHAR(30) NOT NULL,
+  interval VARCHAR(10) NOT NULL,
+  row_limit INTEGER NOT NULL,
+  start_time BIGINT NOT NULL,
+  end_time BIGINT NOT NULL,
+  validate BOOLEAN NOT NULL DEFAULT FALSE,
+  audit BOOLEAN NOT NULL DEFAULT FALSE,
+  status VARCHAR(10) NOT NULL DEFAULT 'pending',
+  attempts INTEGER NOT NULL DEFAULT 0,
+  max_attempts INTEGER NOT NULL,
+  available_at TIMESTAMP NOT NULL,
+  run_id VARCHAR(32),
+  error TEXT,
+  created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  UNIQUE (job, source, symbol, interval, start_time)
+);
+
+SELECT diesel_manage_updated_at('beholder_tasks');
+
+CREATE INDEX beholder_tasks_claim ON beholder_tasks (status, available_at);
@@ -0,0 +1,8 @@
+DROP VIEW binance_open_interest_summaries_view_1d;
+DROP VIEW binance_open_interest_summaries_view;
+DROP VIEW binance_klines_view_1d_usdm;
+DROP VIEW binance_klines_view_1d_spot;
+DROP VIEW binance_klines_view_1d;
+DROP VIEW binance_klines_view;
+DROP TABLE binance_open_interest_summaries;
+DROP TABLE binance_klines;
@@ -0,0 +1,125 @@
+-- SQLite has no enum types, intervals or NUMERIC casts with fixed precision,
+-- so markets are checked text and view intervals are expressed in seconds.
+CREATE TABLE binance_klines (
+  symbol VARCHAR(30) NOT NULL,
+  open_time BIGINT NOT NULL,
+  close_time BIGINT NOT NULL,
+  source TEXT NOT NULL CHECK (source IN ('SPOT', 'USDM', 'COINM')),
+  open TEXT NOT NULL,
+  high TEXT NOT NULL,
+  low TEXT NOT NULL,
+  close TEXT NOT NULL,
+  base_volume TEXT NOT NULL,
+  quote_volume TEXT NOT NULL,
+  buy_base_volume TEXT NOT NULL,
+  buy_quote_volume TEXT NOT NULL,
+  number_of_trades BIGINT NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (symbol, open_time, close_time, source)
+);
+
+CREATE TRIGGER binance_klines_set_updated_at
+  AFTER UPDATE ON binance_klines
+  FOR EACH ROW WHEN NEW.updated_at IS OLD.updated_at
+BEGIN
+  UPDATE binance_klines SET updated_at = CURRENT_TIMESTAMP WHERE rowid = NEW.rowid;
+END;
+
+CREATE TABLE binance_open_interest_summaries (
+  symbol VARCHAR(30) NOT NULL,
+  interval VARCHAR(10) NOT NULL,
+  timestamp BIGINT NOT NULL,
+  sum_open_interest TEXT NOT NULL,
+  sum_open_interest_value TEXT NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (symbol, interval, timestamp)
+);
+
+CREATE TRIGGER binance_open_interest_summaries_set_updated_at
+  AFTER UPDATE ON binance_open_interest_summaries
+  FOR EACH ROW WHEN NEW.updated_at IS OLD.updated_at
+BEGIN
+  UPDATE binance_open_interest_summaries SET updated_at = CURRENT_TIMESTAMP WHERE rowid = NEW.rowid;
+END;
+
+CREATE VIEW binance_klines_view AS
+  SELECT symbol,
+         source,
+         DATETIME(open_time / 1000, 'unixepoch') AS open_time,
+         (close_time + 1 - open_time) / 1000 AS interval,
+         CAST(open AS NUMERIC) AS open,
+         CAST(high AS NUMERIC) AS high,
+         CAST(low AS NUMERIC) AS low,
+         CAST(close AS NUMERIC) AS close,
+         CAST(base_volume AS NUMERIC) AS base_volume,
+         CAST(buy_base_volume AS NUMERIC) AS buy_base_volume,
+         CAST(quote_volume AS NUMERIC) AS quote_volume,
+         CAST(buy_quote_volume AS NUMERIC) AS buy_quote_volume,
+         number_of_trades,
+         created_at,
+         updated_at
+    FROM binance_klines
+   ORDER BY symbol ASC,
+            open_time ASC;
+
+CREATE VIEW binance_klines_view_1d AS
+  SELECT symbol,
+         source,
+         DATE(open_time) AS date,
+         open,
+         high,
+         low,
+         close,
+         base_volume AS volume,
+         COALESCE(buy_base_volume * 1.0 / NULLIF(base_volume, 0), 0) AS buy_percentage,
+         number_of_trades
+    FROM binance_klines_view
+   WHERE interval = 86400;
+
+CREATE VIEW binance_klines_view_1d_spot AS
+  SELECT symbol,
+         date,
+         open,
+         high,
+         low,
+         close,
+         volume,
+         buy_percentage,
+         number_of_trades
+    FROM binance_klines_view_1d
+   WHERE source = 'SPOT';
+
+CREATE VIEW binance_klines_view_1d_usdm AS
+  SELECT symbol,
+         date,
+         open,
+         high,
+         low,
+         close,
+         volume,
+         buy_percentage,
+         number_of_trades
+    FROM binance_klines_view_1d
+   WHERE source = 'USDM';
+
+CREATE VIEW binance_open_interest_summaries_view AS
+  SELECT symbol,
+         interval,
+         DATETIME(timestamp / 1000, 'unixepoch') AS timestamp,
+         CAST(sum_open_interest AS NUMERIC) AS sum_open_interest,
+         CAST(sum_open_interest_value AS NUMERIC) AS sum_open_interest_value,
+         created_at,
+         updated_at
+    FROM binance_open_interest_summaries
+   ORDER BY symbol ASC,
+            timestamp ASC;
+
+CREATE VIEW binance_open_interest_summaries_view_1d AS
+  SELECT symbol,
+         DATE(timestamp) AS date,
+         sum_open_interest,
+         sum_open_interest_value
+    FROM binance_open_interest_summaries_view
+   WHERE interval = '1d';
@@ -0,0 +1,2 @@
+DROP VIEW binance_local_klines_view;
+DROP TABLE binance_local_klines;
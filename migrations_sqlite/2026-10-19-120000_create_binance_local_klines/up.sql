@@ -0,0 +1,50 @@
+-- Daily and weekly klines aggregated in a local timezone, sessions open
+-- `session_start` milliseconds after local midnight.
+CREATE TABLE binance_local_klines (
+  symbol VARCHAR(30) NOT NULL,
+  source TEXT NOT NULL CHECK (source IN ('SPOT', 'USDM', 'COINM')),
+  timezone VARCHAR(64) NOT NULL,
+  session_start BIGINT NOT NULL,
+  interval VARCHAR(10) NOT NULL,
+  local_date DATE NOT NULL,
+  open_time BIGINT NOT NULL,
+  close_time BIGINT NOT NULL,
+  open TEXT NOT NULL,
+  high TEXT NOT NULL,
+  low TEXT NOT NULL,
+  close TEXT NOT NULL,
+  base_volume TEXT NOT NULL,
+  quote_volume TEXT NOT NULL,
+  buy_base_volume TEXT NOT NULL,
+  buy_quote_volume TEXT NOT NULL,
+  number_of_trades BIGINT NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (symbol, source, timezone, session_start, interval, local_date)
+);
+
+CREATE TRIGGER binance_local_klines_set_updated_at
+  AFTER UPDATE ON binance_local_klines
+  FOR EACH ROW WHEN NEW.updated_at IS OLD.updated_at
+BEGIN
+  UPDATE binance_local_klines SET updated_at = CURRENT_TIMESTAMP WHERE rowid = NEW.rowid;
+END;
+
+CREATE VIEW binance_local_klines_view AS
+  SELECT symbol,
+         source,
+         timezone,
+         session_start / 1000 AS session_start,
+         interval,
+         local_date AS date,
+         DATETIME(open_time / 1000, 'unixepoch') AS open_time,
+         open,
+         high,
+         low,
+         close,
+         base_volume AS volume,
+         COALESCE(buy_base_volume * 1.0 / NULLIF(base_volume, 0), 0) AS buy_percentage,
+         number_of_trades
+    FROM binance_local_klines
+   ORDER BY symbol ASC,
+            local_date ASC;
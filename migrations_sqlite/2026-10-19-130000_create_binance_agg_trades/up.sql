@@ -0,0 +1,13 @@
+CREATE TABLE binance_agg_trades (
+  symbol VARCHAR(30) NOT NULL,
+  source TEXT NOT NULL CHECK (source IN ('SPOT', 'USDM', 'COINM')),
+  agg_trade_id BIGINT NOT NULL,
+  price TEXT NOT NULL,
+  quantity TEXT NOT NULL,
+  first_trade_id BIGINT NOT NULL,
+  last_trade_id BIGINT NOT NULL,
+  transact_time BIGINT NOT NULL,
+  is_buyer_maker BOOLEAN NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (symbol, source, agg_trade_id)
+);
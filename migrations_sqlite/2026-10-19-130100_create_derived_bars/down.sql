@@ -0,0 +1 @@
+DROP TABLE derived_bars;
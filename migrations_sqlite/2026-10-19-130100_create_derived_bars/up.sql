@@ -0,0 +1,31 @@
+-- Bars built from aggregated trades. `bar_type` is one of time, volume,
+-- dollar or tick and `size` the interval or threshold of the bar.
+CREATE TABLE derived_bars (
+  symbol VARCHAR(30) NOT NULL,
+  source TEXT NOT NULL CHECK (source IN ('SPOT', 'USDM', 'COINM')),
+  bar_type VARCHAR(10) NOT NULL,
+  size VARCHAR(40) NOT NULL,
+  first_agg_trade_id BIGINT NOT NULL,
+  last_agg_trade_id BIGINT NOT NULL,
+  open_time BIGINT NOT NULL,
+  close_time BIGINT NOT NULL,
+  open TEXT NOT NULL,
+  high TEXT NOT NULL,
+  low TEXT NOT NULL,
+  close TEXT NOT NULL,
+  base_volume TEXT NOT NULL,
+  quote_volume TEXT NOT NULL,
+  buy_base_volume TEXT NOT NULL,
+  buy_quote_volume TEXT NOT NULL,
+  number_of_trades BIGINT NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (symbol, source, bar_type, size, first_agg_trade_id)
+);
+
+CREATE TRIGGER derived_bars_set_updated_at
+  AFTER UPDATE ON derived_bars
+  FOR EACH ROW WHEN NEW.updated_at IS OLD.updated_at
+BEGIN
+  UPDATE derived_bars SET updated_at = CURRENT_TIMESTAMP WHERE rowid = NEW.rowid;
+END;
@@ -0,0 +1 @@
+DROP TABLE binance_funding_rates;
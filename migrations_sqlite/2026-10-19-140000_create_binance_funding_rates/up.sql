@@ -0,0 +1,16 @@
+CREATE TABLE binance_funding_rates (
+  symbol VARCHAR(30) NOT NULL,
+  funding_time BIGINT NOT NULL,
+  funding_rate TEXT NOT NULL,
+  mark_price TEXT,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (symbol, funding_time)
+);
+
+CREATE TRIGGER binance_funding_rates_set_updated_at
+  AFTER UPDATE ON binance_funding_rates
+  FOR EACH ROW WHEN NEW.updated_at IS OLD.updated_at
+BEGIN
+  UPDATE binance_funding_rates SET updated_at = CURRENT_TIMESTAMP WHERE rowid = NEW.rowid;
+END;
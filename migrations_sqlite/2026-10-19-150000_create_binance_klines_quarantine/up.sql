@@ -0,0 +1,19 @@
+CREATE TABLE binance_klines_quarantine (
+  symbol VARCHAR(30) NOT NULL,
+  open_time BIGINT NOT NULL,
+  close_time BIGINT NOT NULL,
+  source TEXT NOT NULL CHECK (source IN ('SPOT', 'USDM', 'COINM')),
+  open TEXT NOT NULL,
+  high TEXT NOT NULL,
+  low TEXT NOT NULL,
+  close TEXT NOT NULL,
+  base_volume TEXT NOT NULL,
+  quote_volume TEXT NOT NULL,
+  buy_base_volume TEXT NOT NULL,
+  buy_quote_volume TEXT NOT NULL,
+  number_of_trades BIGINT NOT NULL,
+  derived BOOLEAN NOT NULL,
+  rules TEXT NOT NULL,
+  quarantined_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (symbol, open_time, close_time, source)
+);
@@ -0,0 +1 @@
+DROP TABLE binance_kline_revisions;
@@ -0,0 +1,23 @@
+CREATE TABLE beholder_runs (
+  id VARCHAR(32) PRIMARY KEY NOT NULL,
+  command VARCHAR(64) NOT NULL,
+  arguments TEXT NOT NULL,
+  config_hash VARCHAR(64),
+  status VARCHAR(10) NOT NULL,
+  rows_written BIGINT NOT NULL DEFAULT 0,
+  errors TEXT,
+  error TEXT,
+  started_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  finished_at TIMESTAMP
+);
+
+CREATE INDEX beholder_runs_command ON beholder_runs (command, started_at);
+
+ALTER TABLE binance_klines ADD COLUMN run_id VARCHAR(32);
+CREATE INDEX binance_klines_run_id ON binance_klines (run_id);
+
+ALTER TABLE binance_open_interest_summaries ADD COLUMN run_id VARCHAR(32);
+CREATE INDEX binance_open_interest_summaries_run_id ON binance_open_interest_summaries (run_id);
+
+ALTER TABLE binance_funding_rates ADD COLUMN run_id VARCHAR(32);
+CREATE INDEX binance_funding_rates_run_id ON binance_funding_rates (run_id);
@@ -0,0 +1,31 @@
+CREATE TABLE beholder_tasks (
+  id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+  job VARCHAR(64) NOT NULL,
+  dataset VARCHAR(20) NOT NULL,
+  source TEXT NOT NULL CHECK (source IN ('SPOT', 'USDM', 'COINM')),
+  symbol VARCHAR(30) NOT NULL,
+  interval VARCHAR(10) NOT NULL,
+  row_limit INTEGER NOT NULL,
+  start_time BIGINT NOT NULL,
+  end_time BIGINT NOT NULL,
+  validate BOOLEAN NOT NULL DEFAULT FALSE,
+  audit BOOLEAN NOT NULL DEFAULT FALSE,
+  status VARCHAR(10) NOT NULL DEFAULT 'pending',
+  attempts INTEGER NOT NULL DEFAULT 0,
+  max_attempts INTEGER NOT NULL,
+  available_at TIMESTAMP NOT NULL,
+  run_id VARCHAR(32),
+  error TEXT,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE (job, source, symbol, interval, start_time)
+);
+
+CREATE TRIGGER beholder_tasks_set_updated_at
+  AFTER UPDATE ON beholder_tasks
+  FOR EACH ROW WHEN NEW.updated_at IS OLD.updated_at
+BEGIN
+  UPDATE beholder_tasks SET updated_at = CURRENT_TIMESTAMP WHERE rowid = NEW.rowid;
+END;
+
+CREATE INDEX beholder_tasks_claim ON beholder_tasks (status, available_at);
@@ -0,0 +1,11 @@
+-- A hypertable cannot be turned back into a plain table in place. This only
+-- removes compression, the tables stay partitioned until dumped and restored.
+SELECT remove_compression_policy('binance_open_interest_summaries', if_exists => TRUE);
+SELECT decompress_chunk(chunk, if_compressed => TRUE)
+  FROM show_chunks('binance_open_interest_summaries') AS chunk;
+ALTER TABLE binance_open_interest_summaries SET (timescaledb.compress = FALSE);
+
+SELECT remove_compression_policy('binance_klines', if_exists => TRUE);
+SELECT decompress_chunk(chunk, if_compressed => TRUE)
+  FROM show_chunks('binance_klines') AS chunk;
+ALTER TABLE binance_klines SET (timescaledb.compress = FALSE);
@@ -0,0 +1,29 @@
+CREATE EXTENSION IF NOT EXISTS timescaledb;
+
+CREATE OR REPLACE FUNCTION unix_now_millis() RETURNS BIGINT AS $$
+  SELECT (EXTRACT(EPOCH FROM NOW()) * 1000)::BIGINT;
+$$ LANGUAGE SQL STABLE;
+
+SELECT create_hypertable('binance_klines', 'open_time',
+                         chunk_time_interval => BIGINT '604800000',
+                         migrate_data => TRUE);
+SELECT set_integer_now_func('binance_klines', 'unix_now_millis');
+
+ALTER TABLE binance_klines SET (
+  timescaledb.compress,
+  timescaledb.compress_segmentby = 'source, symbol',
+  timescaledb.compress_orderby = 'open_time, close_time'
+);
+SELECT add_compression_policy('binance_klines', compress_after => BIGINT '2592000000');
+
+SELECT create_hypertable('binance_open_interest_summaries', 'timestamp',
+                         chunk_time_interval => BIGINT '2592000000',
+                         migrate_data => TRUE);
+SELECT set_integer_now_func('binance_open_interest_summaries', 'unix_now_millis');
+
+ALTER TABLE binance_open_interest_summaries SET (
+  timescaledb.compress,
+  timescaledb.compress_segmentby = 'symbol, interval',
+  timescaledb.compress_orderby = 'timestamp'
+);
+SELECT add_compression_policy('binance_open_interest_summaries', compress_after => BIGINT '2592000000');
@@ -0,0 +1,2 @@
+DROP MATERIALIZED VIEW binance_klines_1d;
+DROP MATERIALIZED VIEW binance_klines_1h;
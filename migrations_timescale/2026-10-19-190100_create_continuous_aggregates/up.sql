@@ -0,0 +1,49 @@
+CREATE MATERIALIZED VIEW binance_klines_1h
+WITH (timescaledb.continuous) AS
+  SELECT symbol,
+         source,
+         time_bucket(BIGINT '3600000', open_time) AS open_time,
+         first(open::NUMERIC, open_time) AS open,
+         MAX(high::NUMERIC) AS high,
+         MIN(low::NUMERIC) AS low,
+         last(close::NUMERIC, open_time) AS close,
+         SUM(base_volume::NUMERIC) AS base_volume,
+         SUM(buy_base_volume::NUMERIC) AS buy_base_volume,
+         SUM(quote_volume::NUMERIC) AS quote_volume,
+         SUM(buy_quote_volume::NUMERIC) AS buy_quote_volume,
+         SUM(number_of_trades) AS number_of_trades,
+         COUNT(*) AS number_of_klines
+    FROM binance_klines
+   WHERE close_time - open_time = 59999
+   GROUP BY symbol, source, time_bucket(BIGINT '3600000', open_time)
+WITH NO DATA;
+
+SELECT add_continuous_aggregate_policy('binance_klines_1h',
+                                       start_offset => BIGINT '259200000',
+                                       end_offset => BIGINT '3600000',
+                                       schedule_interval => INTERVAL '1 hour');
+
+CREATE MATERIALIZED VIEW binance_klines_1d
+WITH (timescaledb.continuous) AS
+  SELECT symbol,
+         source,
+         time_bucket(BIGINT '86400000', open_time) AS open_time,
+         first(open::NUMERIC, open_time) AS open,
+         MAX(high::NUMERIC) AS high,
+         MIN(low::NUMERIC) AS low,
+         last(close::NUMERIC, open_time) AS close,
+         SUM(base_volume::NUMERIC) AS base_volume,
+         SUM(buy_base_volume::NUMERIC) AS buy_base_volume,
+         SUM(quote_volume::NUMERIC) AS quote_volume,
+         SUM(buy_quote_volume::NUMERIC) AS buy_quote_volume,
+         SUM(number_of_trades) AS number_of_trades,
+         COUNT(*) AS number_of_klines
+    FROM binance_klines
+   WHERE close_time - open_time = 59999
+   GROUP BY symbol, source, time_bucket(BIGINT '86400000', open_time)
+WITH NO DATA;
+
+SELECT add_continuous_aggregate_policy('binance_klines_1d',
+                                       start_offset => BIGINT '604800000',
+                                       end_offset => BIGINT '86400000',
+                                       schedule_interval => INTERVAL '1 day');
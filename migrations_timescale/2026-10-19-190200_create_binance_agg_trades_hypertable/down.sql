@@ -0,0 +1,6 @@
+-- As with the other hypertables, only compression is removed. The primary
+-- key keeps transact_time, which a hypertable cannot drop.
+SELECT remove_compression_policy('binance_agg_trades', if_exists => TRUE);
+SELECT decompress_chunk(chunk, if_compressed => TRUE)
+  FROM show_chunks('binance_agg_trades') AS chunk;
+ALTER TABLE binance_agg_trades SET (timescaledb.compress = FALSE);
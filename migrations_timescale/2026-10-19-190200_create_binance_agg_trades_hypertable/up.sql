@@ -0,0 +1,15 @@
+-- Unique indexes of a hypertable must include its time column.
+ALTER TABLE binance_agg_trades DROP CONSTRAINT binance_agg_trades_pkey;
+ALTER TABLE binance_agg_trades ADD PRIMARY KEY (symbol, source, agg_trade_id, transact_time);
+
+SELECT create_hypertable('binance_agg_trades', 'transact_time',
+                         chunk_time_interval => BIGINT '86400000',
+                         migrate_data => TRUE);
+SELECT set_integer_now_func('binance_agg_trades', 'unix_now_millis');
+
+ALTER TABLE binance_agg_trades SET (
+  timescaledb.compress,
+  timescaledb.compress_segmentby = 'source, symbol',
+  timescaledb.compress_orderby = 'agg_trade_id'
+);
+SELECT add_compression_policy('binance_agg_trades', compress_after => BIGINT '604800000');
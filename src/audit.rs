@@ -0,0 +1,165 @@
+use crate::binance::{Kline, MarketEndpoint};
+use crate::database::DbConnection;
+use crate::result::Result;
+use crate::schema::{binance_kline_revisions, binance_klines};
+use diesel::prelude::*;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+/// A change of a stored kline, holding only the columns that differ.
+#[derive(Debug, PartialEq, Insertable)]
+#[diesel(table_name = binance_kline_revisions)]
+struct Revision<'a> {
+    symbol: &'a str,
+    open_time: i64,
+    close_time: i64,
+    source: MarketEndpoint,
+    old_values: String,
+    new_values: String,
+    run_id: &'a str,
+}
+
+/// Columns of `old` that `new` changes, with their old and new values.
+fn changes(old: &Kline, new: &Kline) -> (Map<String, Value>, Map<String, Value>) {
+    let columns = [
+        ("open", Value::from(&*old.open), Value::from(&*new.open)),
+        ("high", Value::from(&*old.high), Value::from(&*new.high)),
+        ("low", Value::from(&*old.low), Value::from(&*new.low)),
+        ("close", Value::from(&*old.close), Value::from(&*new.close)),
+        (
+            "base_volume",
+            Value::from(&*old.base_volume),
+            Value::from(&*new.base_volume),
+        ),
+        (
+            "quote_volume",
+            Value::from(&*old.quote_volume),
+            Value::from(&*new.quote_volume),
+        ),
+        (
+            "buy_base_volume",
+            Value::from(&*old.buy_base_volume),
+            Value::from(&*new.buy_base_volume),
+        ),
+        (
+            "buy_quote_volume",
+            Value::from(&*old.buy_quote_volume),
+            Value::from(&*new.buy_quote_volume),
+        ),
+        (
+            "number_of_trades",
+            Value::from(old.number_of_trades),
+            Value::from(new.number_of_trades),
+        ),
+        (
+            "derived",
+            Value::from(old.derived),
+            Value::from(new.derived),
+        ),
+    ];
+    let mut old_values = Map::new();
+    let mut new_values = Map::new();
+    for (name, old, new) in columns {
+        if old != new {
+            old_values.insert(name.to_owned(), old);
+            new_values.insert(name.to_owned(), new);
+        }
+    }
+    (old_values, new_values)
+}
+
+/// Records into `binance_kline_revisions` how upserting `klines` will change
+/// the stored ones. New klines are not revisions and are skipped.
+pub fn record_revisions(
+    klines: &[Kline],
+    run_id: &str,
+    connection: &mut DbConnection,
+) -> Result<usize> {
+    let mut series: HashMap<(MarketEndpoint, &str), Vec<&Kline>> = HashMap::new();
+    for kline in klines {
+        series
+            .entry((kline.source, &kline.symbol))
+            .or_default()
+            .push(kline);
+    }
+
+    let mut revisions = 0;
+    for ((source, symbol), klines) in series {
+        let first = klines.iter().map(|kline| kline.open_time).min().unwrap();
+        let last = klines.iter().map(|kline| kline.open_time).max().unwrap();
+        let stored: HashMap<(i64, i64), Kline> = binance_klines::table
+            .filter(binance_klines::source.eq(source))
+            .filter(binance_klines::symbol.eq(symbol))
+            .filter(binance_klines::open_time.between(first, last))
+            .select(Kline::as_select())
+            .load::<Kline>(connection)?
+            .into_iter()
+            .map(|kline| ((kline.open_time, kline.close_time), kline))
+            .collect();
+
+        for kline in klines {
+            let Some(old) = stored.get(&(kline.open_time, kline.close_time)) else {
+                continue;
+            };
+            let (old_values, new_values) = changes(old, kline);
+            if old_values.is_empty() {
+                continue;
+            }
+            revisions += diesel::insert_into(binance_kline_revisions::table)
+                .values(Revision {
+                    symbol,
+                    open_time: kline.open_time,
+                    close_time: kline.close_time,
+                    source,
+                    old_values: Value::Object(old_values).to_string(),
+                    new_values: Value::Object(new_values).to_string(),
+                    run_id,
+                })
+                .execute(connection)?;
+        }
+    }
+    Ok(revisions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::changes;
+    use crate::binance::{Kline, MarketEndpoint};
+    use serde_json::json;
+
+    #[test]
+    fn keep_changed_columns_only() {
+        let old = Kline {
+            source: MarketEndpoint::USDM,
+            symbol: "BTCUSDT".into(),
+            open_time: 1704067200000,
+            close_time: 1704067259999,
+            open: "42250.01".into(),
+            high: "42296.08".into(),
+            low: "42241.76".into(),
+            close: "42283.58".into(),
+            base_volume: "21.50000".into(),
+            quote_volume: "909000.50000000".into(),
+            buy_base_volume: "10.25000".into(),
+            buy_quote_volume: "433500.25000000".into(),
+            number_of_trades: 1200,
+            derived: false,
+        };
+        let new = Kline {
+            close: "42290.00".into(),
+            number_of_trades: 1201,
+            ..old.clone()
+        };
+
+        let (old_values, new_values) = changes(&old, &new);
+        assert_eq!(
+            serde_json::Value::Object(old_values),
+            json!({"close": "42283.58", "number_of_trades": 1200})
+        );
+        assert_eq!(
+            serde_json::Value::Object(new_values),
+            json!({"close": "42290.00", "number_of_trades": 1201})
+        );
+        assert!(changes(&old, &old).0.is_empty());
+    }
+}
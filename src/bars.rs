@@ -0,0 +1,433 @@
+use crate::binance::{interval_to_millis, AggTrade, MarketEndpoint};
+use crate::database::DbConnection;
+use crate::resample::{bucket_open_time, decimal};
+use crate::result::{Error, Result};
+use crate::schema::{binance_agg_trades, derived_bars};
+use diesel::prelude::*;
+use log::info;
+use rust_decimal::Decimal;
+
+const BATCH_SIZE: i64 = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ArgEnum)]
+pub enum BarType {
+    Time,
+    Volume,
+    Dollar,
+    Tick,
+}
+
+impl BarType {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Time => "time",
+            Self::Volume => "volume",
+            Self::Dollar => "dollar",
+            Self::Tick => "tick",
+        }
+    }
+}
+
+/// When a bar closes: at the end of its interval, or once its base volume,
+/// quote volume or number of aggregated trades reaches the threshold.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Threshold {
+    Time(i64),
+    Volume(Decimal),
+    Dollar(Decimal),
+    Tick(i64),
+}
+
+/// Bar type and size, such as time bars of `45m` or dollar bars of `1000000`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BarSpec {
+    bar_type: BarType,
+    size: String,
+    threshold: Threshold,
+}
+
+impl BarSpec {
+    pub fn new(bar_type: BarType, size: &str) -> Result<Self> {
+        let error = || Error::ParseStr(size.to_owned());
+        let positive = |value: Decimal| {
+            if value > Decimal::ZERO {
+                Ok(value.normalize())
+            } else {
+                Err(error())
+            }
+        };
+        let threshold = match bar_type {
+            BarType::Time => match interval_to_millis(size)? {
+                interval if interval > 0 => Threshold::Time(interval),
+                _ => return Err(error()),
+            },
+            BarType::Volume => Threshold::Volume(positive(decimal(size)?)?),
+            BarType::Dollar => Threshold::Dollar(positive(decimal(size)?)?),
+            BarType::Tick => match size.parse() {
+                Ok(ticks) if ticks > 0 => Threshold::Tick(ticks),
+                _ => return Err(error()),
+            },
+        };
+        // Sizes are normalized so `100` and `100.0` build the same series.
+        let size = match threshold {
+            Threshold::Volume(value) | Threshold::Dollar(value) => value.to_string(),
+            _ => size.to_owned(),
+        };
+        Ok(Self {
+            bar_type,
+            size,
+            threshold,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Insertable, AsChangeset)]
+#[diesel(table_name = derived_bars)]
+pub struct DerivedBar {
+    pub source: MarketEndpoint,
+    pub symbol: String,
+    pub bar_type: String,
+    pub size: String,
+    pub first_agg_trade_id: i64,
+    pub last_agg_trade_id: i64,
+    pub open_time: i64,
+    pub close_time: i64,
+    pub open: String,
+    pub high: String,
+    pub low: String,
+    pub close: String,
+    pub base_volume: String,
+    pub quote_volume: String,
+    pub buy_base_volume: String,
+    pub buy_quote_volume: String,
+    pub number_of_trades: i64,
+}
+
+impl DerivedBar {
+    pub fn upsert(&self, connection: &mut DbConnection) -> QueryResult<usize> {
+        diesel::insert_into(derived_bars::table)
+            .values(self)
+            .on_conflict((
+                derived_bars::symbol,
+                derived_bars::source,
+                derived_bars::bar_type,
+                derived_bars::size,
+                derived_bars::first_agg_trade_id,
+            ))
+            .do_update()
+            .set(self)
+            .execute(connection)
+    }
+}
+
+/// A bar still taking trades.
+#[derive(Debug)]
+struct OpenBar {
+    first_agg_trade_id: i64,
+    last_agg_trade_id: i64,
+    open_time: i64,
+    close_time: i64,
+    open: Decimal,
+    high: Decimal,
+    low: Decimal,
+    close: Decimal,
+    base_volume: Decimal,
+    quote_volume: Decimal,
+    buy_base_volume: Decimal,
+    buy_quote_volume: Decimal,
+    number_of_trades: i64,
+    ticks: i64,
+}
+
+impl OpenBar {
+    fn new(trade: &AggTrade, price: Decimal) -> Self {
+        Self {
+            first_agg_trade_id: trade.agg_trade_id,
+            last_agg_trade_id: trade.agg_trade_id,
+            open_time: trade.transact_time,
+            close_time: trade.transact_time,
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+            base_volume: Decimal::ZERO,
+            quote_volume: Decimal::ZERO,
+            buy_base_volume: Decimal::ZERO,
+            buy_quote_volume: Decimal::ZERO,
+            number_of_trades: 0,
+            ticks: 0,
+        }
+    }
+
+    fn push(&mut self, trade: &AggTrade, price: Decimal, quantity: Decimal) {
+        let quote = price * quantity;
+        self.last_agg_trade_id = trade.agg_trade_id;
+        self.high = self.high.max(price);
+        self.low = self.low.min(price);
+        self.close = price;
+        self.base_volume += quantity;
+        self.quote_volume += quote;
+        // The buyer is the taker unless it made the market.
+        if !trade.is_buyer_maker {
+            self.buy_base_volume += quantity;
+            self.buy_quote_volume += quote;
+        }
+        self.number_of_trades += trade.last_trade_id - trade.first_trade_id + 1;
+        self.ticks += 1;
+    }
+
+    fn is_full(&self, threshold: Threshold) -> bool {
+        match threshold {
+            Threshold::Time(_) => false,
+            Threshold::Volume(volume) => self.base_volume >= volume,
+            Threshold::Dollar(volume) => self.quote_volume >= volume,
+            Threshold::Tick(ticks) => self.ticks >= ticks,
+        }
+    }
+
+    fn into_bar(self, source: MarketEndpoint, symbol: &str, spec: &BarSpec) -> DerivedBar {
+        DerivedBar {
+            source,
+            symbol: symbol.to_owned(),
+            bar_type: spec.bar_type.name().to_owned(),
+            size: spec.size.to_owned(),
+            first_agg_trade_id: self.first_agg_trade_id,
+            last_agg_trade_id: self.last_agg_trade_id,
+            open_time: self.open_time,
+            close_time: self.close_time,
+            open: self.open.to_string(),
+            high: self.high.to_string(),
+            low: self.low.to_string(),
+            close: self.close.to_string(),
+            base_volume: self.base_volume.normalize().to_string(),
+            quote_volume: self.quote_volume.normalize().to_string(),
+            buy_base_volume: self.buy_base_volume.normalize().to_string(),
+            buy_quote_volume: self.buy_quote_volume.normalize().to_string(),
+            number_of_trades: self.number_of_trades,
+        }
+    }
+}
+
+/// Cuts trades of one series, in id order, into bars. Only closed bars are
+/// returned, the last bar stays open until a later trade closes it.
+struct BarBuilder<'a> {
+    source: MarketEndpoint,
+    symbol: &'a str,
+    spec: &'a BarSpec,
+    bar: Option<OpenBar>,
+}
+
+impl<'a> BarBuilder<'a> {
+    fn new(source: MarketEndpoint, symbol: &'a str, spec: &'a BarSpec) -> Self {
+        Self {
+            source,
+            symbol,
+            spec,
+            bar: None,
+        }
+    }
+
+    fn push(&mut self, trade: &AggTrade, closed: &mut Vec<DerivedBar>) -> Result {
+        let price = decimal(&trade.price)?;
+        let quantity = decimal(&trade.quantity)?;
+
+        let threshold = self.spec.threshold;
+        if let (Threshold::Time(_), Some(bar)) = (threshold, &self.bar) {
+            if trade.transact_time > bar.close_time {
+                self.close(closed);
+            }
+        }
+        let bar = self.bar.get_or_insert_with(|| {
+            let mut bar = OpenBar::new(trade, price);
+            if let Threshold::Time(interval) = threshold {
+                bar.open_time = bucket_open_time(trade.transact_time, interval);
+                bar.close_time = bar.open_time + interval - 1;
+            }
+            bar
+        });
+        bar.push(trade, price, quantity);
+        // Time bars span their interval, other bars their trades.
+        if !matches!(threshold, Threshold::Time(_)) {
+            bar.close_time = trade.transact_time;
+        }
+        if bar.is_full(threshold) {
+            self.close(closed);
+        }
+        Ok(())
+    }
+
+    fn close(&mut self, closed: &mut Vec<DerivedBar>) {
+        if let Some(bar) = self.bar.take() {
+            closed.push(bar.into_bar(self.source, self.symbol, self.spec));
+        }
+    }
+}
+
+/// Builds bars from the stored aggregated trades of each series, resuming
+/// after the last trade of the latest stored bar. With `rebuild` the series'
+/// bars are deleted first, which picks up trades imported out of order.
+pub fn build_bars(
+    connection: &mut DbConnection,
+    market: Option<MarketEndpoint>,
+    symbols: &[String],
+    spec: &BarSpec,
+    rebuild: bool,
+) -> Result<usize> {
+    let mut query = binance_agg_trades::table
+        .select((binance_agg_trades::source, binance_agg_trades::symbol))
+        .distinct()
+        .into_boxed();
+    if let Some(market) = market {
+        query = query.filter(binance_agg_trades::source.eq(market));
+    }
+    if !symbols.is_empty() {
+        query = query.filter(binance_agg_trades::symbol.eq_any(symbols));
+    }
+    let series: Vec<(MarketEndpoint, String)> = query
+        .order((binance_agg_trades::source, binance_agg_trades::symbol))
+        .load(connection)?;
+
+    let mut written = 0;
+    for (source, symbol) in series {
+        let bars = || {
+            derived_bars::table
+                .filter(derived_bars::source.eq(source))
+                .filter(derived_bars::symbol.eq(&symbol))
+                .filter(derived_bars::bar_type.eq(spec.bar_type.name()))
+                .filter(derived_bars::size.eq(&spec.size))
+        };
+        if rebuild {
+            diesel::delete(bars()).execute(connection)?;
+        }
+        let mut last: Option<i64> = bars()
+            .select(diesel::dsl::max(derived_bars::last_agg_trade_id))
+            .first(connection)?;
+
+        info!(
+            "Building {} bars of {} for {} {}...",
+            spec.bar_type.name(),
+            spec.size,
+            source,
+            symbol
+        );
+        let mut builder = BarBuilder::new(source, &symbol, spec);
+        let mut count = 0;
+        loop {
+            let mut query = binance_agg_trades::table
+                .filter(binance_agg_trades::source.eq(source))
+                .filter(binance_agg_trades::symbol.eq(&symbol))
+                .into_boxed();
+            if let Some(last) = last {
+                query = query.filter(binance_agg_trades::agg_trade_id.gt(last));
+            }
+            let trades: Vec<AggTrade> = query
+                .select(AggTrade::as_select())
+                .order(binance_agg_trades::agg_trade_id)
+                .limit(BATCH_SIZE)
+                .load(connection)?;
+
+            let mut closed = Vec::new();
+            for trade in &trades {
+                builder.push(trade, &mut closed)?;
+            }
+            count += connection.transaction(|connection| {
+                closed
+                    .iter()
+                    .map(|bar| bar.upsert(connection))
+                    .sum::<QueryResult<usize>>()
+            })?;
+
+            match trades.last() {
+                Some(trade) if (trades.len() as i64) == BATCH_SIZE => {
+                    last = Some(trade.agg_trade_id)
+                }
+                _ => break,
+            }
+        }
+        info!("Wrote {} bars for {} {}", count, source, symbol);
+        written += count;
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BarBuilder, BarSpec, BarType};
+    use crate::binance::{AggTrade, MarketEndpoint};
+
+    fn trade(id: i64, time: i64, price: &str, quantity: &str, is_buyer_maker: bool) -> AggTrade {
+        AggTrade {
+            source: MarketEndpoint::USDM,
+            symbol: "BTCUSDT".into(),
+            agg_trade_id: id,
+            price: price.into(),
+            quantity: quantity.into(),
+            first_trade_id: id * 10,
+            last_trade_id: id * 10 + 1,
+            transact_time: time,
+            is_buyer_maker,
+        }
+    }
+
+    fn build(spec: &BarSpec, trades: &[AggTrade]) -> Vec<super::DerivedBar> {
+        let mut builder = BarBuilder::new(MarketEndpoint::USDM, "BTCUSDT", spec);
+        let mut closed = Vec::new();
+        for trade in trades {
+            builder.push(trade, &mut closed).unwrap();
+        }
+        closed
+    }
+
+    #[test]
+    fn build_time_bars() {
+        let spec = BarSpec::new(BarType::Time, "2m").unwrap();
+        let bars = build(
+            &spec,
+            &[
+                trade(1, 10_000, "100", "1", false),
+                trade(2, 110_000, "102", "2", true),
+                trade(3, 130_000, "99", "1", false),
+                trade(4, 250_000, "101", "1", false),
+            ],
+        );
+
+        assert_eq!(bars.len(), 2);
+        assert_eq!((bars[0].open_time, bars[0].close_time), (0, 119_999));
+        assert_eq!(bars[0].high, "102");
+        assert_eq!(bars[0].close, "102");
+        assert_eq!(bars[0].base_volume, "3");
+        assert_eq!(bars[0].quote_volume, "304");
+        assert_eq!(bars[0].buy_base_volume, "1");
+        assert_eq!(bars[0].number_of_trades, 4);
+        assert_eq!(bars[1].first_agg_trade_id, 3);
+        assert_eq!(bars[1].open_time, 120_000);
+    }
+
+    #[test]
+    fn build_threshold_bars() {
+        let trades = [
+            trade(1, 1, "100", "0.5", false),
+            trade(2, 2, "100", "0.6", true),
+            trade(3, 3, "200", "1", false),
+            trade(4, 4, "200", "1", false),
+        ];
+
+        let spec = BarSpec::new(BarType::Volume, "1.0").unwrap();
+        assert_eq!(spec.size, "1");
+        let bars = build(&spec, &trades);
+        assert_eq!(bars.len(), 3);
+        assert_eq!(bars[0].base_volume, "1.1");
+        assert_eq!((bars[0].open_time, bars[0].close_time), (1, 2));
+
+        let bars = build(&BarSpec::new(BarType::Dollar, "200").unwrap(), &trades);
+        assert_eq!(bars.len(), 2);
+        assert_eq!(bars[1].quote_volume, "200");
+
+        let bars = build(&BarSpec::new(BarType::Tick, "3").unwrap(), &trades);
+        assert_eq!(bars.len(), 1);
+        assert_eq!(bars[0].last_agg_trade_id, 3);
+
+        assert!(BarSpec::new(BarType::Tick, "0").is_err());
+        assert!(BarSpec::new(BarType::Time, "0m").is_err());
+    }
+}
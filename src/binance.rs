@@ -1,42 +1,70 @@
+use crate::audit::record_revisions;
+use crate::database::{with_connection, DbConnection, DbPool};
+use crate::recording::Recording;
+use crate::rest::RestClient;
 use crate::result::{Error, Result};
-use crate::schema::{binance_klines, binance_open_interest_summaries};
+use crate::runs;
+use crate::schema::{
+    binance_agg_trades, binance_funding_rates, binance_klines, binance_open_interest_summaries,
+};
+use crate::validate::quarantine_invalid;
 use binance_client::{
-    api::Binance,
-    futures::{
-        market::FuturesMarket as FutureEndpoint,
-        model::OpenInterestHist,
-        websockets::{
-            FuturesMarket, FuturesWebSockets as FutureWebSocket,
-            FuturesWebsocketEvent as FutureWebSocketEvent,
-        },
-    },
-    market::Market as SpotEndpoint,
-    model::{KlineEvent, KlineSummaries, KlineSummary},
-    websockets::{WebSockets as SpotWebSocket, WebsocketEvent as SpotWebSocketEvent},
+    futures::model::OpenInterestHist,
+    model::{KlineEvent, KlineSummary},
 };
-use diesel::pg::{upsert::on_constraint, Pg, PgConnection};
+use diesel::deserialize::{self, FromSql};
+use diesel::pg::{Pg, PgValue};
 use diesel::prelude::*;
 use diesel::serialize::{self, IsNull, Output, ToSql};
+#[cfg(feature = "sqlite")]
+use diesel::sqlite::{Sqlite, SqliteValue};
 use diesel::Insertable;
-use log::{info, warn};
+use futures_util::{future, StreamExt};
+use log::{debug, info, warn};
 use serde::Deserialize;
-use std::sync::atomic::AtomicBool;
 use std::{
+    collections::HashSet,
+    fmt,
     fs::OpenOptions,
     io::{BufReader, Write},
     path::Path,
     str::FromStr,
+    time::Duration,
     vec::Vec,
 };
+use tokio::sync::mpsc;
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+/// Streams per websocket connection, Binance accepts up to 1024 but a long
+/// URL or a busy connection delays every stream on it.
+const STREAMS_PER_CONNECTION: usize = 200;
+
+/// Complete klines queued for the database before sockets wait.
+const KLINE_BUFFER: usize = 4_096;
+
+/// Klines upserted in one statement.
+const WRITE_BATCH: usize = 500;
+
+/// Page size Binance uses for funding rates when a request sets no `limit`.
+const FUNDING_RATE_DEFAULT_LIMIT: u16 = 100;
+
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
 
-#[derive(SqlType)]
+#[derive(SqlType, QueryId)]
 #[diesel(postgres_type(name = "market"))]
+#[diesel(sqlite_type(name = "Text"))]
 pub struct Market;
 
-#[derive(Debug, PartialEq, AsExpression, Clone, Copy, clap::ArgEnum)]
+#[derive(
+    Debug, PartialEq, Eq, Hash, AsExpression, FromSqlRow, Clone, Copy, clap::ArgEnum, Deserialize,
+)]
 #[diesel(sql_type = Market)]
+#[serde(rename_all = "lowercase")]
 pub enum MarketEndpoint {
     Spot,
+    /// Spelled like Binance's USDⓈ-M futures
+    #[allow(clippy::upper_case_acronyms)]
     USDM,
 }
 
@@ -50,99 +78,349 @@ impl ToSql<Market, Pg> for MarketEndpoint {
     }
 }
 
+#[cfg(feature = "sqlite")]
+impl ToSql<Market, Sqlite> for MarketEndpoint {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        match *self {
+            MarketEndpoint::Spot => out.set_value("SPOT"),
+            MarketEndpoint::USDM => out.set_value("USDM"),
+        }
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Market, Pg> for MarketEndpoint {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match bytes.as_bytes() {
+            b"SPOT" => Ok(MarketEndpoint::Spot),
+            b"USDM" => Ok(MarketEndpoint::USDM),
+            _ => Err("Unrecognized market variant".into()),
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl FromSql<Market, Sqlite> for MarketEndpoint {
+    fn from_sql(value: SqliteValue<'_, '_, '_>) -> deserialize::Result<Self> {
+        let text = <String as FromSql<diesel::sql_types::Text, Sqlite>>::from_sql(value)?;
+        match text.as_str() {
+            "SPOT" => Ok(MarketEndpoint::Spot),
+            "USDM" => Ok(MarketEndpoint::USDM),
+            _ => Err("Unrecognized market variant".into()),
+        }
+    }
+}
+
+/// The fields of an `exchangeInfo` symbol needed to select symbols.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ListedSymbol {
+    symbol: String,
+    status: String,
+    quote_asset: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExchangeSymbols {
+    symbols: Vec<ListedSymbol>,
+}
+
+/// Spot archives switched to microsecond timestamps in 2025, anything above
+/// this cannot be a millisecond timestamp.
+const MICROSECOND_THRESHOLD: i64 = 100_000_000_000_000;
+
+/// Milliseconds of a timestamp given in milliseconds or microseconds.
+pub fn millis(time: i64) -> i64 {
+    if time >= MICROSECOND_THRESHOLD {
+        time / 1_000
+    } else {
+        time
+    }
+}
+
+/// A kline as returned by the REST API and stored in kline archives:
+/// `open_time,open,high,low,close,volume,close_time,quote_volume,count,
+/// taker_buy_volume,taker_buy_quote_volume,ignore`
+#[derive(Debug, Deserialize)]
+pub struct RawKline(
+    i64,
+    String,
+    String,
+    String,
+    String,
+    String,
+    i64,
+    String,
+    i64,
+    String,
+    String,
+    serde::de::IgnoredAny,
+);
+
+impl From<RawKline> for KlineSummary {
+    fn from(row: RawKline) -> Self {
+        Self {
+            open_time: millis(row.0),
+            open: row.1,
+            high: row.2,
+            low: row.3,
+            close: row.4,
+            volume: row.5,
+            close_time: millis(row.6),
+            quote_asset_volume: row.7,
+            number_of_trades: row.8,
+            taker_buy_base_asset_volume: row.9,
+            taker_buy_quote_asset_volume: row.10,
+        }
+    }
+}
+
 impl MarketEndpoint {
-    pub fn fetch(
+    /// Symbols currently trading against `quote_asset`, such as every `USDT`
+    /// pair.
+    pub async fn symbols_quoted_in(
+        &self,
+        client: &RestClient,
+        quote_asset: &str,
+    ) -> Result<Vec<String>> {
+        let exchange: ExchangeSymbols = match self {
+            Self::Spot => client.get(*self, "/api/v3/exchangeInfo", &[], 20).await?,
+            Self::USDM => client.get(*self, "/fapi/v1/exchangeInfo", &[], 1).await?,
+        };
+        Ok(exchange
+            .symbols
+            .into_iter()
+            .filter(|symbol| symbol.status == "TRADING" && symbol.quote_asset == quote_asset)
+            .map(|symbol| symbol.symbol)
+            .collect())
+    }
+
+    /// Request weight of a kline request returning `limit` klines.
+    fn klines_weight(&self, limit: u16) -> u32 {
+        match (self, limit) {
+            (Self::Spot, _) => 2,
+            (Self::USDM, 0..=99) => 1,
+            (Self::USDM, 100..=499) => 2,
+            (Self::USDM, 500..=1000) => 5,
+            (Self::USDM, _) => 10,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub async fn fetch(
         &self,
+        client: &RestClient,
         query: &KlineQuery,
         interval: Option<String>,
         limit: Option<u16>,
         start_time: Option<u64>,
         end_time: Option<u64>,
-        connection: &mut PgConnection,
+        pool: &DbPool,
+        options: &StoreOptions,
     ) -> Result {
         let symbol = query.symbol.to_owned();
         let interval = interval.unwrap_or(query.interval.to_owned());
         let limit = limit.unwrap_or(query.limit);
-        let KlineSummaries::AllKlineSummaries(summaries) = match self {
-            MarketEndpoint::Spot => {
-                info!("Downloading {}@{} from Binance Spot...", symbol, interval);
-                let market: SpotEndpoint = Binance::new(None, None);
-                market.get_klines(symbol, interval, limit, start_time, end_time)?
-            }
-            MarketEndpoint::USDM => {
-                info!("Downloading {}@{} from Binance USDM...", symbol, interval);
-                let market: FutureEndpoint = Binance::new(None, None);
-                market.get_klines(symbol, interval, limit, start_time, end_time)?
-            }
+        info!(
+            "Downloading {}@{} from Binance {}...",
+            symbol, interval, self
+        );
+
+        let path = match self {
+            Self::Spot => "/api/v3/klines",
+            Self::USDM => "/fapi/v1/klines",
         };
-        for summary in summaries {
-            Kline::from_kline_summary(query.symbol.to_owned(), *self, summary)
-                .upsert(connection)?;
+        // Without a start Binance returns the latest klines, with one it pages
+        // forward until a short page
+        let mut start_time = start_time;
+        loop {
+            let mut parameters = vec![
+                ("symbol", symbol.to_owned()),
+                ("interval", interval.to_owned()),
+                ("limit", limit.to_string()),
+            ];
+            parameters.extend(time_range(start_time, end_time));
+            let klines: Vec<RawKline> = client
+                .get(*self, path, &parameters, self.klines_weight(limit))
+                .await?;
+            debug!(
+                "Received {} klines of {}@{}",
+                klines.len(),
+                symbol,
+                interval
+            );
+
+            let full_page = klines.len() == usize::from(limit);
+            let klines: Vec<Kline> = klines
+                .into_iter()
+                .map(|kline| Kline::from_kline_summary(symbol.to_owned(), *self, kline.into()))
+                .collect();
+            let next_start = klines.last().map(|kline| kline.open_time as u64 + 1);
+            let options = options.clone();
+            with_connection(pool, move |connection| {
+                Kline::store(klines, connection, &options)?;
+                Ok(())
+            })
+            .await?;
+
+            match (start_time, next_start) {
+                (Some(_), Some(next_start)) if full_page => start_time = Some(next_start),
+                _ => return Ok(()),
+            }
         }
-        Ok(())
     }
 
-    pub fn watch(
+    /// Stores the complete klines of every query until a write fails or a
+    /// replay ends. Topics are spread over connections of
+    /// `STREAMS_PER_CONNECTION` streams, which reconnect on their own, and
+    /// share one bounded queue to the database.
+    pub async fn watch(
         &self,
+        client: &RestClient,
         queries: &[KlineQuery],
         interval: Option<String>,
-        connection: &mut PgConnection,
-    ) {
+        pool: &DbPool,
+        options: &StoreOptions,
+    ) -> Result {
         let topics: Vec<String> = queries
-            .into_iter()
+            .iter()
             .map(|query| {
                 let interval = interval.as_ref().unwrap_or(&query.interval);
                 format!("{}@kline_{}", query.symbol.to_lowercase(), interval)
             })
             .collect();
         info!("Listen on topics: {:?}", topics);
-        let keep_running = AtomicBool::new(true);
 
-        match self {
-            Self::Spot => {
-                let mut web_socket: SpotWebSocket =
-                    SpotWebSocket::new(|event: SpotWebSocketEvent| {
-                        if let SpotWebSocketEvent::Kline(kline_event) = event {
-                            if kline_event.kline.is_final_bar {
-                                let kline: Kline = Kline::from_kline_event(*self, kline_event);
-                                info!("Complete Kline received: {:?}", kline);
-                                kline.upsert(connection).unwrap();
-                            } else {
-                                info!("Incomplete Kline received: {:?}", kline_event);
-                            }
-                        } else {
-                            warn!("Unexpected Spot WS Event: {:?}", event);
-                        };
-                        Ok(())
-                    });
-                web_socket.connect_multiple_streams(&topics).unwrap();
-                web_socket.event_loop(&keep_running).unwrap();
-                web_socket.disconnect().unwrap();
+        let (sender, receiver) = mpsc::channel(KLINE_BUFFER);
+        let listeners = future::join_all(
+            topics
+                .chunks(STREAMS_PER_CONNECTION)
+                .map(|topics| self.listen(client, topics, sender.clone())),
+        );
+        drop(sender);
+        let writer = write_klines(receiver, pool, options);
+        tokio::pin!(writer);
+        tokio::select! {
+            _ = listeners => writer.await,
+            result = &mut writer => result,
+        }
+    }
+
+    /// Forwards complete klines of `topics` to `sender` until the receiver is
+    /// gone or every recorded connection was replayed. Closed connections are
+    /// reopened after a second, failing ones after growing delays.
+    async fn listen(&self, client: &RestClient, topics: &[String], sender: mpsc::Sender<Kline>) {
+        let stream = format!("/stream?streams={}", topics.join("/"));
+        let url = format!("{}{}", client.ws_base_url(*self), stream);
+        let recording = client.recording();
+
+        if let Some(recording) = recording.filter(|recording| recording.is_replay()) {
+            while let Some(frames) = recording.next_connection(&stream) {
+                for text in frames {
+                    if let Err(error) = self.forward(&text, &sender).await {
+                        warn!("Cannot replay a frame of {}: {}", stream, error);
+                    }
+                }
+            }
+            return;
+        }
+
+        let mut delay = MIN_RECONNECT_DELAY;
+        while !sender.is_closed() {
+            let connection =
+                recording.map(|recording| (recording, recording.open_connection(&stream)));
+            match self.read_stream(&url, &stream, connection, &sender).await {
+                Ok(()) => {
+                    warn!("Binance {} closed a stream", self);
+                    delay = MIN_RECONNECT_DELAY;
+                }
+                Err(error) => warn!("Binance {} stream failed: {}", self, error),
             }
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+        }
+    }
 
-            Self::USDM => {
-                let mut web_socket: FutureWebSocket =
-                    FutureWebSocket::new(|event: FutureWebSocketEvent| {
-                        if let FutureWebSocketEvent::Kline(kline_event) = event {
-                            if kline_event.kline.is_final_bar {
-                                let kline: Kline = Kline::from_kline_event(*self, kline_event);
-                                info!("Complete Kline received: {:?}", kline);
-                                kline.upsert(connection).unwrap();
-                            } else {
-                                info!("Incomplete Kline received: {:?}", kline_event);
-                            }
-                        } else {
-                            warn!("Unexpected USDM WS Event: {:?}", event);
-                        };
-                        Ok(())
-                    });
-                web_socket
-                    .connect_multiple_streams(&FuturesMarket::USDM, &topics)
-                    .unwrap();
-                web_socket.event_loop(&keep_running).unwrap();
-                web_socket.disconnect().unwrap();
+    async fn read_stream(
+        &self,
+        url: &str,
+        stream: &str,
+        connection: Option<(&Recording, usize)>,
+        sender: &mpsc::Sender<Kline>,
+    ) -> Result {
+        let (mut socket, _) = connect_async(url).await?;
+        while let Some(message) = socket.next().await {
+            let text = match message? {
+                Message::Text(text) => text,
+                Message::Close(_) => break,
+                _ => continue,
+            };
+            if let Some((recording, connection)) = connection {
+                recording.save_frame(stream, connection, &text)?;
+            }
+            if !self.forward(&text, sender).await? {
+                break;
             }
         }
+        Ok(())
+    }
+
+    /// Sends the kline of a frame to `sender` once complete, false when the
+    /// receiver is gone.
+    async fn forward(&self, text: &str, sender: &mpsc::Sender<Kline>) -> Result<bool> {
+        let event = serde_json::from_str::<StreamEvent>(text)?.data;
+        if !event.kline.is_final_bar {
+            debug!("Incomplete Kline received: {:?}", event);
+            return Ok(true);
+        }
+        let kline = Kline::from_kline_event(*self, event);
+        info!("Complete Kline received: {:?}", kline);
+        Ok(sender.send(kline).await.is_ok())
+    }
+}
+
+/// A message of a combined stream.
+#[derive(Debug, Deserialize)]
+struct StreamEvent {
+    data: KlineEvent,
+}
+
+/// Upserts klines in batches of those queued while the previous batch was
+/// written, keeping the last of duplicates.
+async fn write_klines(
+    mut receiver: mpsc::Receiver<Kline>,
+    pool: &DbPool,
+    options: &StoreOptions,
+) -> Result {
+    while let Some(kline) = receiver.recv().await {
+        let mut batch = vec![kline];
+        while batch.len() < WRITE_BATCH {
+            match receiver.try_recv() {
+                Ok(kline) => batch.push(kline),
+                Err(_) => break,
+            }
+        }
+        let mut keys = HashSet::new();
+        batch.reverse();
+        batch.retain(|kline| {
+            keys.insert((kline.symbol.to_owned(), kline.open_time, kline.close_time))
+        });
+        let options = options.clone();
+        with_connection(pool, move |connection| {
+            Kline::store(batch, connection, &options)?;
+            Ok(())
+        })
+        .await?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for MarketEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Spot => write!(f, "SPOT"),
+            Self::USDM => write!(f, "USDM"),
+        }
     }
 }
 
@@ -158,29 +436,57 @@ impl FromStr for MarketEndpoint {
     }
 }
 
-#[derive(Debug, PartialEq, Insertable, AsChangeset)]
+/// Checks and bookkeeping around storing fetched klines.
+#[derive(Debug, Clone, clap::Args)]
+pub struct StoreOptions {
+    /// Quarantine klines breaking a validation rule instead of storing them
+    #[clap(long, action)]
+    pub validate: bool,
+
+    /// Record changes of stored klines in binance_kline_revisions
+    #[clap(long, action)]
+    pub audit: bool,
+
+    /// Identifies the rows and revisions written by this run
+    #[clap(skip = runs::run_id())]
+    pub run_id: String,
+}
+
+impl Default for StoreOptions {
+    fn default() -> Self {
+        Self {
+            validate: false,
+            audit: false,
+            run_id: runs::run_id(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Insertable, AsChangeset, Queryable, Selectable)]
 #[diesel(table_name = binance_klines)]
 pub struct Kline {
-    source: MarketEndpoint,
-    symbol: String,
-    open_time: i64,
-    close_time: i64,
-    open: String,
-    high: String,
-    low: String,
-    close: String,
-    base_volume: String,
-    quote_volume: String,
-    buy_base_volume: String,
-    buy_quote_volume: String,
-    number_of_trades: i64,
+    pub source: MarketEndpoint,
+    pub symbol: String,
+    pub open_time: i64,
+    pub close_time: i64,
+    pub open: String,
+    pub high: String,
+    pub low: String,
+    pub close: String,
+    pub base_volume: String,
+    pub quote_volume: String,
+    pub buy_base_volume: String,
+    pub buy_quote_volume: String,
+    pub number_of_trades: i64,
+    /// Aggregated from shorter klines rather than fetched
+    pub derived: bool,
 }
 
 impl Kline {
     pub fn from_kline_summary(symbol: String, source: MarketEndpoint, kline: KlineSummary) -> Self {
         Self {
-            source: source,
-            symbol: symbol,
+            source,
+            symbol,
             open_time: kline.open_time,
             close_time: kline.close_time,
             open: kline.open,
@@ -192,6 +498,7 @@ impl Kline {
             buy_base_volume: kline.taker_buy_base_asset_volume,
             buy_quote_volume: kline.taker_buy_quote_asset_volume,
             number_of_trades: kline.number_of_trades,
+            derived: false,
         }
     }
 
@@ -199,7 +506,7 @@ impl Kline {
         let kline = event.kline;
 
         Self {
-            source: source,
+            source,
             symbol: kline.symbol,
             open_time: kline.open_time,
             close_time: kline.close_time,
@@ -212,17 +519,189 @@ impl Kline {
             buy_base_volume: kline.taker_buy_base_asset_volume,
             buy_quote_volume: kline.taker_buy_quote_asset_volume,
             number_of_trades: kline.number_of_trades,
+            derived: false,
         }
     }
 
-    pub fn upsert(&self, connection: &mut PgConnection) -> QueryResult<usize> {
+    /// Upserts the kline, tagging it with the run that wrote it.
+    pub fn upsert(
+        &self,
+        run_id: Option<&str>,
+        connection: &mut DbConnection,
+    ) -> QueryResult<usize> {
+        let run_id = binance_klines::run_id.eq(run_id);
+        diesel::insert_into(binance_klines::table)
+            .values((self, run_id))
+            .on_conflict((
+                binance_klines::symbol,
+                binance_klines::open_time,
+                binance_klines::close_time,
+                binance_klines::source,
+            ))
+            .do_update()
+            .set((self, run_id))
+            .execute(connection)
+    }
+
+    /// Upserts many klines in a single statement.
+    #[cfg(not(feature = "sqlite"))]
+    pub fn upsert_all(
+        klines: &[Self],
+        run_id: Option<&str>,
+        connection: &mut DbConnection,
+    ) -> QueryResult<usize> {
+        use diesel::upsert::excluded;
+
+        let rows: Vec<_> = klines
+            .iter()
+            .map(|kline| (kline, binance_klines::run_id.eq(run_id)))
+            .collect();
         diesel::insert_into(binance_klines::table)
-            .values(self)
-            .on_conflict(on_constraint("binance_klines_pkey"))
+            .values(rows)
+            .on_conflict((
+                binance_klines::symbol,
+                binance_klines::open_time,
+                binance_klines::close_time,
+                binance_klines::source,
+            ))
             .do_update()
-            .set(self)
+            .set((
+                binance_klines::open.eq(excluded(binance_klines::open)),
+                binance_klines::high.eq(excluded(binance_klines::high)),
+                binance_klines::low.eq(excluded(binance_klines::low)),
+                binance_klines::close.eq(excluded(binance_klines::close)),
+                binance_klines::base_volume.eq(excluded(binance_klines::base_volume)),
+                binance_klines::quote_volume.eq(excluded(binance_klines::quote_volume)),
+                binance_klines::buy_base_volume.eq(excluded(binance_klines::buy_base_volume)),
+                binance_klines::buy_quote_volume.eq(excluded(binance_klines::buy_quote_volume)),
+                binance_klines::number_of_trades.eq(excluded(binance_klines::number_of_trades)),
+                binance_klines::derived.eq(excluded(binance_klines::derived)),
+                binance_klines::run_id.eq(excluded(binance_klines::run_id)),
+            ))
             .execute(connection)
     }
+
+    /// Upserts `klines` in one transaction after the checks of `options`.
+    pub fn store(
+        klines: Vec<Self>,
+        connection: &mut DbConnection,
+        options: &StoreOptions,
+    ) -> Result<usize> {
+        connection.transaction(|connection| {
+            let klines = match options.validate {
+                true => quarantine_invalid(klines, connection)?,
+                false => klines,
+            };
+            if options.audit {
+                record_revisions(&klines, &options.run_id, connection)?;
+            }
+            Ok(Self::upsert_all(
+                &klines,
+                Some(&options.run_id),
+                connection,
+            )?)
+        })
+    }
+
+    /// Diesel cannot combine batch inserts with upserts on SQLite, so rows
+    /// are upserted one by one.
+    #[cfg(feature = "sqlite")]
+    pub fn upsert_all(
+        klines: &[Self],
+        run_id: Option<&str>,
+        connection: &mut DbConnection,
+    ) -> QueryResult<usize> {
+        klines
+            .iter()
+            .map(|kline| kline.upsert(run_id, connection))
+            .sum()
+    }
+}
+
+/// Trades filled by the same taker order at the same price.
+#[derive(Debug, Clone, PartialEq, Insertable, Queryable, Selectable)]
+#[diesel(table_name = binance_agg_trades)]
+pub struct AggTrade {
+    pub source: MarketEndpoint,
+    pub symbol: String,
+    pub agg_trade_id: i64,
+    pub price: String,
+    pub quantity: String,
+    pub first_trade_id: i64,
+    pub last_trade_id: i64,
+    pub transact_time: i64,
+    pub is_buyer_maker: bool,
+}
+
+impl AggTrade {
+    /// Inserts many trades in a single statement, trades never change so
+    /// known ones are left alone.
+    #[cfg(not(feature = "sqlite"))]
+    pub fn insert_all(trades: &[Self], connection: &mut DbConnection) -> QueryResult<usize> {
+        diesel::insert_into(binance_agg_trades::table)
+            .values(trades)
+            .on_conflict_do_nothing()
+            .execute(connection)
+    }
+
+    /// Diesel cannot combine batch inserts with conflict clauses on SQLite,
+    /// so rows are inserted one by one.
+    #[cfg(feature = "sqlite")]
+    pub fn insert_all(trades: &[Self], connection: &mut DbConnection) -> QueryResult<usize> {
+        trades
+            .iter()
+            .map(|trade| {
+                diesel::insert_into(binance_agg_trades::table)
+                    .values(trade)
+                    .on_conflict_do_nothing()
+                    .execute(connection)
+            })
+            .sum()
+    }
+}
+
+/// Length of a Binance kline interval such as `15m` or `1d` in milliseconds.
+/// Monthly intervals have no fixed length and are rejected.
+pub fn interval_to_millis(interval: &str) -> Result<i64> {
+    let error = || Error::ParseStr(interval.to_owned());
+    let unit = match interval.chars().last().ok_or_else(error)? {
+        's' => 1_000,
+        'm' => 60_000,
+        'h' => 3_600_000,
+        'd' => 86_400_000,
+        'w' => 604_800_000,
+        _ => return Err(error()),
+    };
+    let count: i64 = interval[..interval.len() - 1]
+        .parse()
+        .map_err(|_| error())?;
+    Ok(count * unit)
+}
+
+/// The shortest interval name for a length in milliseconds, the inverse of
+/// `interval_to_millis`.
+pub fn millis_to_interval(millis: i64) -> String {
+    [
+        ('w', 604_800_000),
+        ('d', 86_400_000),
+        ('h', 3_600_000),
+        ('m', 60_000),
+        ('s', 1_000),
+    ]
+    .iter()
+    .find(|(_, unit)| millis > 0 && millis % unit == 0)
+    .map(|(name, unit)| format!("{}{}", millis / unit, name))
+    .unwrap_or_else(|| format!("{}ms", millis))
+}
+
+/// The optional `startTime` and `endTime` parameters of a request.
+fn time_range(
+    start_time: Option<u64>,
+    end_time: Option<u64>,
+) -> impl Iterator<Item = (&'static str, String)> {
+    let start_time = start_time.map(|time| ("startTime", time.to_string()));
+    let end_time = end_time.map(|time| ("endTime", time.to_string()));
+    start_time.into_iter().chain(end_time)
 }
 
 #[derive(Debug, Deserialize, PartialEq)]
@@ -232,6 +711,16 @@ pub struct KlineQuery {
     pub limit: u16,
 }
 
+/// `symbol@interval`, or just the symbol for queries without an interval.
+impl fmt::Display for KlineQuery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.interval.as_str() {
+            "" => write!(f, "{}", self.symbol),
+            interval => write!(f, "{}@{}", self.symbol, interval),
+        }
+    }
+}
+
 impl KlineQuery {
     pub fn from_csv<P>(path: P) -> Result<Vec<Self>>
     where
@@ -243,14 +732,14 @@ impl KlineQuery {
     }
 }
 
-#[derive(Debug, PartialEq, Insertable, AsChangeset)]
+#[derive(Debug, PartialEq, Insertable, AsChangeset, Queryable, Selectable)]
 #[diesel(table_name = binance_open_interest_summaries)]
 pub struct OpenInterestSummary {
-    symbol: String,
-    interval: String,
-    sum_open_interest: String,
-    sum_open_interest_value: String,
-    timestamp: i64,
+    pub symbol: String,
+    pub interval: String,
+    pub sum_open_interest: String,
+    pub sum_open_interest_value: String,
+    pub timestamp: i64,
 }
 
 impl OpenInterestSummary {
@@ -264,51 +753,174 @@ impl OpenInterestSummary {
         })
     }
 
-    fn upsert(&self, connection: &mut PgConnection) -> QueryResult<usize> {
+    fn upsert(&self, run_id: &str, connection: &mut DbConnection) -> QueryResult<usize> {
+        let run_id = binance_open_interest_summaries::run_id.eq(run_id);
         diesel::insert_into(binance_open_interest_summaries::table)
-            .values(self)
-            .on_conflict(on_constraint("binance_open_interest_summaries_pkey"))
+            .values((self, run_id))
+            .on_conflict((
+                binance_open_interest_summaries::symbol,
+                binance_open_interest_summaries::interval,
+                binance_open_interest_summaries::timestamp,
+            ))
             .do_update()
-            .set(self)
+            .set((self, run_id))
             .execute(connection)
     }
 
-    pub fn fetch(
+    /// Fetches the summaries of `query` and stores them as written by
+    /// `run_id`.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn fetch(
+        client: &RestClient,
         query: &KlineQuery,
         interval: Option<String>,
         limit: Option<u16>,
         start_time: Option<u64>,
         end_time: Option<u64>,
-        connection: &mut PgConnection,
+        pool: &DbPool,
+        run_id: &str,
     ) -> Result {
-        let market: FutureEndpoint = Binance::new(None, None);
         let symbol = &query.symbol;
         let interval = interval.unwrap_or(query.interval.to_owned());
+        let limit = limit.unwrap_or(query.limit);
 
         info!(
             "Downloading open interest summary of {}@{} ...",
             symbol, interval
         );
 
-        let hists: Vec<OpenInterestHist> = market.open_interest_statistics(
-            symbol.to_owned(),
-            interval.to_owned(),
-            limit,
-            start_time,
-            end_time,
-        )?;
+        // Like klines, a request with a start pages forward until a short page
+        let mut start_time = start_time;
+        loop {
+            let mut parameters = vec![
+                ("symbol", symbol.to_owned()),
+                ("period", interval.to_owned()),
+                ("limit", limit.to_string()),
+            ];
+            parameters.extend(time_range(start_time, end_time));
+            let hists: Vec<OpenInterestHist> = client
+                .get(
+                    MarketEndpoint::USDM,
+                    "/futures/data/openInterestHist",
+                    &parameters,
+                    1,
+                )
+                .await?;
+
+            let full_page = hists.len() == usize::from(limit);
+            let summaries = hists
+                .into_iter()
+                .map(|hist| Self::from_open_interest_hist(interval.to_owned(), hist))
+                .collect::<Result<Vec<_>>>()?;
+            let next_start = summaries.last().map(|summary| summary.timestamp as u64 + 1);
+            let run_id = run_id.to_owned();
+            with_connection(pool, move |connection| {
+                for summary in summaries {
+                    summary.upsert(&run_id, connection)?;
+                }
+                Ok(())
+            })
+            .await?;
 
-        for hist in hists {
-            Self::from_open_interest_hist(interval.to_owned(), hist)?.upsert(connection)?;
+            match (start_time, next_start) {
+                (Some(_), Some(next_start)) if full_page => start_time = Some(next_start),
+                _ => return Ok(()),
+            }
         }
+    }
+}
 
-        Ok(())
+/// An entry of `/fapi/v1/fundingRate`, the mark price is empty for old
+/// settlements.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FundingRateHist {
+    symbol: String,
+    funding_time: i64,
+    funding_rate: String,
+    #[serde(default)]
+    mark_price: String,
+}
+
+#[derive(Debug, PartialEq, Insertable, AsChangeset, Queryable, Selectable)]
+#[diesel(table_name = binance_funding_rates)]
+pub struct FundingRate {
+    pub symbol: String,
+    pub funding_time: i64,
+    pub funding_rate: String,
+    pub mark_price: Option<String>,
+}
+
+impl From<FundingRateHist> for FundingRate {
+    fn from(hist: FundingRateHist) -> Self {
+        Self {
+            symbol: hist.symbol,
+            funding_time: hist.funding_time,
+            funding_rate: hist.funding_rate,
+            mark_price: Some(hist.mark_price).filter(|price| !price.is_empty()),
+        }
+    }
+}
+
+impl FundingRate {
+    fn upsert(&self, run_id: &str, connection: &mut DbConnection) -> QueryResult<usize> {
+        let run_id = binance_funding_rates::run_id.eq(run_id);
+        diesel::insert_into(binance_funding_rates::table)
+            .values((self, run_id))
+            .on_conflict((
+                binance_funding_rates::symbol,
+                binance_funding_rates::funding_time,
+            ))
+            .do_update()
+            .set((self, run_id))
+            .execute(connection)
+    }
+
+    pub async fn fetch(
+        client: &RestClient,
+        symbol: &str,
+        limit: Option<u16>,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+        pool: &DbPool,
+        run_id: &str,
+    ) -> Result {
+        info!("Downloading funding rates of {} ...", symbol);
+
+        let mut start_time = start_time;
+        loop {
+            let mut parameters = vec![("symbol", symbol.to_owned())];
+            parameters.extend(limit.map(|limit| ("limit", limit.to_string())));
+            parameters.extend(time_range(start_time, end_time));
+            let hists: Vec<FundingRateHist> = client
+                .get(MarketEndpoint::USDM, "/fapi/v1/fundingRate", &parameters, 1)
+                .await?;
+
+            let full_page = hists.len() == usize::from(limit.unwrap_or(FUNDING_RATE_DEFAULT_LIMIT));
+            let next_start = hists.last().map(|hist| hist.funding_time as u64 + 1);
+            let run_id = run_id.to_owned();
+            with_connection(pool, move |connection| {
+                for hist in hists {
+                    Self::from(hist).upsert(&run_id, connection)?;
+                }
+                Ok(())
+            })
+            .await?;
+
+            match (start_time, next_start) {
+                (Some(_), Some(next_start)) if full_page => start_time = Some(next_start),
+                _ => return Ok(()),
+            }
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Kline, KlineQuery, MarketEndpoint, OpenInterestSummary};
+    use super::{
+        interval_to_millis, millis_to_interval, FundingRate, FundingRateHist, Kline, KlineQuery,
+        MarketEndpoint, OpenInterestSummary, StreamEvent,
+    };
     use binance_client::futures::model::OpenInterestHist;
     use binance_client::model::{self, KlineEvent, KlineSummary};
 
@@ -342,6 +954,7 @@ mod tests {
             buy_base_volume: "buy base volume".into(),
             buy_quote_volume: "buy quote volume".into(),
             number_of_trades: 333,
+            derived: false,
         };
 
         assert_eq!(
@@ -391,6 +1004,7 @@ mod tests {
             buy_base_volume: "buy base volume".into(),
             buy_quote_volume: "buy quote volume".into(),
             number_of_trades: 333,
+            derived: false,
         };
 
         assert_eq!(
@@ -441,4 +1055,90 @@ mod tests {
             OpenInterestSummary::from_open_interest_hist("1d".into(), hist).unwrap()
         );
     }
+
+    #[test]
+    fn convert_interval_to_millis_and_back() {
+        for (interval, millis) in [
+            ("1s", 1_000),
+            ("15m", 900_000),
+            ("4h", 14_400_000),
+            ("1w", 604_800_000),
+        ] {
+            assert_eq!(interval_to_millis(interval).unwrap(), millis);
+            assert_eq!(millis_to_interval(millis), interval);
+        }
+        assert_eq!(millis_to_interval(86_400_000), "1d");
+        assert!(interval_to_millis("1M").is_err());
+        assert!(interval_to_millis("m").is_err());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn upsert_kline_into_sqlite() {
+        use crate::database::{self, DbConnection, Migrations};
+        use crate::schema::binance_klines;
+        use diesel::prelude::*;
+
+        let mut connection = DbConnection::establish(":memory:").unwrap();
+        database::migrate_up(&mut connection, Migrations::default()).unwrap();
+
+        let mut kline = Kline {
+            source: MarketEndpoint::USDM,
+            symbol: "BTCUSDT".into(),
+            open_time: 0,
+            close_time: 59999,
+            open: "1".into(),
+            high: "3".into(),
+            low: "0.5".into(),
+            close: "2".into(),
+            base_volume: "10".into(),
+            quote_volume: "20".into(),
+            buy_base_volume: "4".into(),
+            buy_quote_volume: "8".into(),
+            number_of_trades: 5,
+            derived: false,
+        };
+        kline.upsert(None, &mut connection).unwrap();
+        kline.close = "2.5".into();
+        kline.upsert(None, &mut connection).unwrap();
+
+        let closes: Vec<String> = binance_klines::table
+            .select(binance_klines::close)
+            .load(&mut connection)
+            .unwrap();
+        assert_eq!(closes, vec!["2.5".to_owned()]);
+    }
+
+    #[test]
+    fn create_funding_rate_from_hist() {
+        let hists: Vec<FundingRateHist> = serde_json::from_str(
+            r#"[
+                {"symbol":"BTCUSDT","fundingTime":1570608000000,"fundingRate":"-0.03750000","markPrice":""},
+                {"symbol":"BTCUSDT","fundingTime":1704096000000,"fundingRate":"0.00037409","markPrice":"42513.90000000"}
+            ]"#,
+        )
+        .unwrap();
+        let rates: Vec<FundingRate> = hists.into_iter().map(FundingRate::from).collect();
+
+        assert_eq!(rates[0].mark_price, None);
+        assert_eq!(rates[1].funding_time, 1704096000000);
+        assert_eq!(rates[1].mark_price.as_deref(), Some("42513.90000000"));
+    }
+
+    #[test]
+    fn read_combined_stream_event() {
+        let event: StreamEvent = serde_json::from_str(
+            r#"{"stream":"btcusdt@kline_1m","data":{"e":"kline","E":1704067260001,"s":"BTCUSDT","k":{
+                "t":1704067200000,"T":1704067259999,"s":"BTCUSDT","i":"1m","f":100,"L":200,
+                "o":"42283.58","c":"42300.00","h":"42310.00","l":"42280.00","v":"12.5","n":101,
+                "x":true,"q":"528700.00","V":"6.2","Q":"262200.00","B":"0"}}}"#,
+        )
+        .unwrap();
+        assert!(event.data.kline.is_final_bar);
+
+        let kline = Kline::from_kline_event(MarketEndpoint::Spot, event.data);
+        assert_eq!(kline.symbol, "BTCUSDT");
+        assert_eq!(kline.close_time, 1704067259999);
+        assert_eq!(kline.number_of_trades, 101);
+    }
 }
@@ -0,0 +1,418 @@
+use crate::binance::{
+    interval_to_millis, millis, AggTrade, Kline, MarketEndpoint, RawKline, StoreOptions,
+};
+use crate::database::DbConnection;
+use crate::result::{Error, Result};
+use diesel::Connection;
+use log::{info, warn};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+};
+
+/// Rows are upserted in chunks to stay well below the bind parameter limit.
+const CHUNK_SIZE: usize = 1_000;
+
+/// A row of an aggTrades archive:
+/// `agg_trade_id,price,quantity,first_trade_id,last_trade_id,transact_time,
+/// is_buyer_maker[,is_best_match]`. Spot archives spell booleans `True`.
+#[derive(Debug, Deserialize)]
+struct ArchivedAggTrade(i64, String, String, i64, i64, i64, String);
+
+impl ArchivedAggTrade {
+    fn into_agg_trade(self, symbol: &str, source: MarketEndpoint) -> AggTrade {
+        AggTrade {
+            source,
+            symbol: symbol.to_owned(),
+            agg_trade_id: self.0,
+            price: self.1,
+            quantity: self.2,
+            first_trade_id: self.3,
+            last_trade_id: self.4,
+            transact_time: millis(self.5),
+            is_buyer_maker: self.6.eq_ignore_ascii_case("true"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    pub imported: Vec<PathBuf>,
+    pub skipped: Vec<PathBuf>,
+    pub failed: Vec<(PathBuf, Error)>,
+    pub klines: usize,
+    pub trades: usize,
+}
+
+#[derive(Debug, PartialEq)]
+enum Archive {
+    Klines { symbol: String, interval: String },
+    AggTrades { symbol: String },
+}
+
+fn collect_archives(path: &Path, archives: &mut Vec<PathBuf>) -> Result {
+    if path.is_dir() {
+        for entry in fs::read_dir(path)? {
+            collect_archives(&entry?.path(), archives)?;
+        }
+    } else if path.extension().is_some_and(|extension| extension == "zip") {
+        archives.push(path.to_owned());
+    }
+    Ok(())
+}
+
+/// Infers the market from the `data/spot/…` or `data/futures/um/…` layout of
+/// the public dump.
+fn market_from_path(path: &Path) -> Option<MarketEndpoint> {
+    let components: Vec<_> = path
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect();
+    components
+        .windows(2)
+        .find_map(|pair| match (&*pair[0], &*pair[1]) {
+            ("spot", _) => Some(MarketEndpoint::Spot),
+            ("futures", "um") => Some(MarketEndpoint::USDM),
+            _ => None,
+        })
+}
+
+/// Parses names such as `BTCUSDT-1m-2023-01.zip` or
+/// `BTCUSDT-aggTrades-2023-01-01.zip`, or `None` for archives holding
+/// anything else, such as `BTCUSDT-trades-2023-01.zip`.
+fn parse_archive_name(path: &Path) -> Option<Archive> {
+    let stem = path.file_stem()?.to_str()?;
+    let mut parts = stem.split('-');
+    let symbol = parts.next()?.to_owned();
+    match parts.next()? {
+        "aggTrades" => Some(Archive::AggTrades { symbol }),
+        interval => {
+            interval_to_millis(interval).ok()?;
+            Some(Archive::Klines {
+                symbol,
+                interval: interval.to_owned(),
+            })
+        }
+    }
+}
+
+fn verify_checksum(path: &Path) -> Result {
+    let mut checksum_path = path.as_os_str().to_owned();
+    checksum_path.push(".CHECKSUM");
+    let checksum = fs::read_to_string(&checksum_path)?;
+    let expected = checksum
+        .split_whitespace()
+        .next()
+        .ok_or_else(|| Error::ParseStr(checksum.to_owned()))?;
+
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    let actual: String = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    if actual.eq_ignore_ascii_case(expected) {
+        Ok(())
+    } else {
+        Err(Error::Checksum(path.to_owned()))
+    }
+}
+
+/// Calls `f` with each data row of the CSV files in an archive, reading
+/// them as they are decompressed.
+fn each_record(path: &Path, mut f: impl FnMut(csv::StringRecord) -> Result) -> Result {
+    let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+
+    for index in 0..archive.len() {
+        let file = archive.by_index(index)?;
+        if !file.name().ends_with(".csv") {
+            continue;
+        }
+
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(file);
+        for record in reader.records() {
+            let record = record?;
+            // Futures archives carry a header row, spot archives do not.
+            if record
+                .get(0)
+                .is_none_or(|field| field.parse::<i64>().is_err())
+            {
+                continue;
+            }
+            f(record)?;
+        }
+    }
+    Ok(())
+}
+
+/// Parses the rows of an archive with `parse` and passes them to `f`
+/// `CHUNK_SIZE` at a time, so archives of several gigabytes never sit in
+/// memory whole. Returns the rows read.
+fn each_chunk<T>(
+    path: &Path,
+    mut parse: impl FnMut(csv::StringRecord) -> Result<T>,
+    mut f: impl FnMut(Vec<T>) -> Result,
+) -> Result<usize> {
+    let mut rows = Vec::with_capacity(CHUNK_SIZE);
+    let mut count = 0;
+    each_record(path, |record| {
+        rows.push(parse(record)?);
+        count += 1;
+        if rows.len() == CHUNK_SIZE {
+            f(std::mem::replace(&mut rows, Vec::with_capacity(CHUNK_SIZE)))?;
+        }
+        Ok(())
+    })?;
+    if !rows.is_empty() {
+        f(rows)?;
+    }
+    Ok(count)
+}
+
+fn parse_kline(record: csv::StringRecord, symbol: &str, market: MarketEndpoint) -> Result<Kline> {
+    let row: RawKline = record.deserialize(None)?;
+    Ok(Kline::from_kline_summary(
+        symbol.to_owned(),
+        market,
+        row.into(),
+    ))
+}
+
+fn parse_agg_trade(
+    mut record: csv::StringRecord,
+    symbol: &str,
+    market: MarketEndpoint,
+) -> Result<AggTrade> {
+    record.truncate(7);
+    let row: ArchivedAggTrade = record.deserialize(None)?;
+    Ok(row.into_agg_trade(symbol, market))
+}
+
+fn import_archive(
+    connection: &mut DbConnection,
+    path: &Path,
+    archive: &Archive,
+    market: MarketEndpoint,
+    verify: bool,
+    options: &StoreOptions,
+    summary: &mut ImportSummary,
+) -> Result {
+    if verify {
+        verify_checksum(path)?;
+    }
+
+    match archive {
+        Archive::Klines { symbol, .. } => {
+            summary.klines += connection.transaction(|connection| {
+                each_chunk(
+                    path,
+                    |record| parse_kline(record, symbol, market),
+                    |klines| {
+                        Kline::store(klines, connection, options)?;
+                        Ok(())
+                    },
+                )
+            })?;
+        }
+        Archive::AggTrades { symbol } => {
+            summary.trades += connection.transaction(|connection| {
+                each_chunk(
+                    path,
+                    |record| parse_agg_trade(record, symbol, market),
+                    |trades| {
+                        AggTrade::insert_all(&trades, connection)?;
+                        Ok(())
+                    },
+                )
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Imports kline and aggTrades archives downloaded from
+/// https://data.binance.vision. Each path may be an archive or a directory
+/// searched recursively for archives.
+pub fn import(
+    connection: &mut DbConnection,
+    paths: &[PathBuf],
+    market: Option<MarketEndpoint>,
+    verify: bool,
+    options: &StoreOptions,
+) -> Result<ImportSummary> {
+    let mut archives = Vec::new();
+    for path in paths {
+        collect_archives(path, &mut archives)?;
+    }
+    archives.sort();
+
+    let mut summary = ImportSummary::default();
+    for path in archives {
+        let Some(archive) = parse_archive_name(&path) else {
+            warn!("Skip {}, not a kline or aggTrades archive", path.display());
+            summary.skipped.push(path);
+            continue;
+        };
+        let Some(market) = market.or_else(|| market_from_path(&path)) else {
+            warn!("Skip {}, cannot infer its market", path.display());
+            summary.skipped.push(path);
+            continue;
+        };
+
+        match &archive {
+            Archive::Klines { symbol, interval } => {
+                info!(
+                    "Importing {}@{} from {}...",
+                    symbol,
+                    interval,
+                    path.display()
+                )
+            }
+            Archive::AggTrades { symbol } => {
+                info!("Importing {} trades from {}...", symbol, path.display())
+            }
+        }
+        match import_archive(
+            connection,
+            &path,
+            &archive,
+            market,
+            verify,
+            options,
+            &mut summary,
+        ) {
+            Ok(()) => summary.imported.push(path),
+            Err(error) => {
+                warn!("Import of {} failed: {}", path.display(), error);
+                summary.failed.push((path, error));
+            }
+        }
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        each_chunk, market_from_path, parse_agg_trade, parse_archive_name, Archive,
+        ArchivedAggTrade, CHUNK_SIZE,
+    };
+    use crate::binance::{MarketEndpoint, RawKline};
+    use binance_client::model::KlineSummary;
+    use std::{fs::File, io::Write, path::Path};
+
+    #[test]
+    fn parse_archive_path() {
+        let path = Path::new("data/futures/um/monthly/klines/BTCUSDT/1m/BTCUSDT-1m-2023-01.zip");
+        assert_eq!(market_from_path(path), Some(MarketEndpoint::USDM));
+        assert_eq!(
+            parse_archive_name(path),
+            Some(Archive::Klines {
+                symbol: "BTCUSDT".into(),
+                interval: "1m".into()
+            })
+        );
+
+        let path = Path::new("data/spot/daily/aggTrades/ETHBTC/ETHBTC-aggTrades-2023-01-01.zip");
+        assert_eq!(market_from_path(path), Some(MarketEndpoint::Spot));
+        assert_eq!(
+            parse_archive_name(path),
+            Some(Archive::AggTrades {
+                symbol: "ETHBTC".into()
+            })
+        );
+
+        let path = Path::new("data/spot/daily/trades/ETHBTC/ETHBTC-trades-2023-01-01.zip");
+        assert_eq!(parse_archive_name(path), None);
+    }
+
+    #[test]
+    fn read_microsecond_spot_row() {
+        let row = "1735689600000000,93576.00,93610.93,93537.50,93610.93,8.21827,1735689659999999,768978.81,2631,3.95454,370018.83,0";
+        let record: RawKline = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(row.as_bytes())
+            .deserialize()
+            .next()
+            .unwrap()
+            .unwrap();
+        let summary = KlineSummary::from(record);
+
+        assert_eq!(summary.open_time, 1735689600000);
+        assert_eq!(summary.close_time, 1735689659999);
+        assert_eq!(summary.taker_buy_quote_asset_volume, "370018.83");
+    }
+
+    #[test]
+    fn read_spot_agg_trade_row() {
+        let row = "3354417291,93576.00,0.00320,4376463812,4376463812,1735689600008331,True,True";
+        let mut record = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(row.as_bytes())
+            .records()
+            .next()
+            .unwrap()
+            .unwrap();
+        record.truncate(7);
+        let row: ArchivedAggTrade = record.deserialize(None).unwrap();
+        let trade = row.into_agg_trade("BTCUSDT", MarketEndpoint::Spot);
+
+        assert_eq!(trade.agg_trade_id, 3354417291);
+        assert_eq!(trade.quantity, "0.00320");
+        assert_eq!(trade.transact_time, 1735689600008);
+        assert!(trade.is_buyer_maker);
+    }
+
+    #[test]
+    fn read_archive_in_chunks() {
+        let path = std::env::temp_dir().join(format!(
+            "beholder-{}-BTCUSDT-aggTrades-2024-01.zip",
+            std::process::id()
+        ));
+        let mut archive = zip::ZipWriter::new(File::create(&path).unwrap());
+        archive
+            .start_file("BTCUSDT-aggTrades-2024-01.csv", Default::default())
+            .unwrap();
+        writeln!(
+            archive,
+            "agg_trade_id,price,quantity,first_trade_id,last_trade_id,transact_time,is_buyer_maker"
+        )
+        .unwrap();
+        let rows = CHUNK_SIZE * 2 + 500;
+        for id in 0..rows {
+            writeln!(
+                archive,
+                "{},42000.1,0.002,{},{},1704067200000,true",
+                id, id, id
+            )
+            .unwrap();
+        }
+        archive.finish().unwrap();
+
+        let mut chunks = Vec::new();
+        let count = each_chunk(
+            &path,
+            |record| parse_agg_trade(record, "BTCUSDT", MarketEndpoint::USDM),
+            |trades| {
+                chunks.push((trades[0].agg_trade_id, trades.len()));
+                Ok(())
+            },
+        )
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(count, rows);
+        assert_eq!(
+            chunks,
+            vec![(0, CHUNK_SIZE), (1_000, CHUNK_SIZE), (2_000, 500)]
+        );
+    }
+}
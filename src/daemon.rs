@@ -0,0 +1,113 @@
+use crate::binance::interval_to_millis;
+use crate::database::DbPool;
+use crate::jobs::{Job, Schedule};
+use crate::lock::Locks;
+use crate::rest::RestClient;
+use crate::result::Result;
+use chrono::{DateTime, TimeDelta, Utc};
+use futures_util::future;
+use log::{info, warn};
+use rand::Rng;
+
+/// Next scheduled run after `time`, delayed by a random part of `jitter`
+/// milliseconds.
+fn next_run(schedule: &Schedule, jitter: i64, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let jitter = match jitter {
+        0 => 0,
+        jitter => rand::thread_rng().gen_range(0..=jitter),
+    };
+    Some(schedule.next_after(time)? + TimeDelta::milliseconds(jitter))
+}
+
+/// Runs `job` on its schedule until the process stops. Runs of one job never
+/// overlap: the next run is planned only once the previous one finished, so
+/// runs missed meanwhile are skipped rather than queued.
+async fn run_scheduled(
+    job: &Job,
+    schedule: &Schedule,
+    config_hash: &str,
+    locks: &Locks,
+    pool: &DbPool,
+    client: &RestClient,
+    concurrency: usize,
+) -> Result {
+    let jitter = job.jitter.as_deref().map_or(Ok(0), interval_to_millis)?;
+
+    while let Some(next) = next_run(schedule, jitter, Utc::now()) {
+        info!("Job {} runs next at {}", job.name, next.to_rfc3339());
+        if let Ok(wait) = (next - Utc::now()).to_std() {
+            tokio::time::sleep(wait).await;
+        }
+
+        match job
+            .run("daemon", config_hash, locks, pool, client, concurrency)
+            .await
+        {
+            Ok(report) if report.skipped => {
+                info!("Job {} skipped, another run holds its lock", job.name)
+            }
+            Ok(report) => {
+                for (query, error) in &report.failed {
+                    warn!("Job {} failed on {}: {}", job.name, query, error);
+                }
+                info!(
+                    "Job {} ran {} queries, {} failed",
+                    job.name,
+                    report.queries,
+                    report.failed.len()
+                );
+            }
+            Err(error) => warn!("Job {} failed: {}", job.name, error),
+        }
+    }
+    Ok(())
+}
+
+/// Runs every scheduled job concurrently, sharing the connection pool and
+/// REST client with its rate limiter. Jobs without a schedule are skipped.
+pub async fn run(
+    jobs: &[Job],
+    config_hash: &str,
+    locks: &Locks,
+    pool: &DbPool,
+    client: &RestClient,
+    concurrency: usize,
+) -> Result {
+    let scheduled = jobs.iter().filter_map(|job| match &job.schedule {
+        Some(schedule) => Some(run_scheduled(
+            job,
+            schedule,
+            config_hash,
+            locks,
+            pool,
+            client,
+            concurrency,
+        )),
+        None => {
+            warn!("Job {} has no schedule, skipped", job.name);
+            None
+        }
+    });
+    future::try_join_all(scheduled).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::next_run;
+    use crate::jobs::Schedule;
+    use chrono::{TimeDelta, TimeZone, Utc};
+
+    #[test]
+    fn jitter_next_run() {
+        let schedule = Schedule::Every(60_000);
+        let time = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 30).unwrap();
+        let planned = Utc.with_ymd_and_hms(2024, 1, 1, 0, 1, 0).unwrap();
+
+        assert_eq!(next_run(&schedule, 0, time), Some(planned));
+        for _ in 0..100 {
+            let next = next_run(&schedule, 5_000, time).unwrap();
+            assert!(next >= planned && next <= planned + TimeDelta::seconds(5));
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use crate::result::{Error, Result};
+use diesel::migration::{self, Migration, MigrationSource};
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use log::info;
+
+#[cfg(not(feature = "sqlite"))]
+pub type DbBackend = diesel::pg::Pg;
+#[cfg(not(feature = "sqlite"))]
+pub type DbConnection = diesel::pg::PgConnection;
+
+#[cfg(feature = "sqlite")]
+pub type DbBackend = diesel::sqlite::Sqlite;
+#[cfg(feature = "sqlite")]
+pub type DbConnection = diesel::sqlite::SqliteConnection;
+
+pub type DbPool = Pool<ConnectionManager<DbConnection>>;
+
+pub fn pool(database_url: &str, size: u32) -> Result<DbPool> {
+    Ok(Pool::builder()
+        .max_size(size)
+        .build(ConnectionManager::new(database_url))?)
+}
+
+/// Runs `f` with a pooled connection on the blocking threads of the runtime,
+/// so queries overlap with network I/O without stalling other tasks.
+pub async fn with_connection<T, F>(pool: &DbPool, f: F) -> Result<T>
+where
+    F: FnOnce(&mut DbConnection) -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    let pool = pool.clone();
+    tokio::task::spawn_blocking(move || f(&mut *pool.get()?))
+        .await
+        .expect("Database task panicked")
+}
+
+#[cfg(not(feature = "sqlite"))]
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+#[cfg(feature = "sqlite")]
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations_sqlite");
+
+/// Hypertables, compression and continuous aggregates, applied on top of
+/// `MIGRATIONS` for databases running TimescaleDB. They are numbered after
+/// every plain migration, which then never alter a hypertable.
+#[cfg(not(feature = "sqlite"))]
+pub const TIMESCALE_MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations_timescale");
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Migrations {
+    pub timescale: bool,
+}
+
+impl MigrationSource<DbBackend> for Migrations {
+    fn migrations(&self) -> migration::Result<Vec<Box<dyn Migration<DbBackend>>>> {
+        let mut migrations = MigrationSource::<DbBackend>::migrations(&MIGRATIONS)?;
+        if self.timescale {
+            #[cfg(not(feature = "sqlite"))]
+            migrations.extend(MigrationSource::<DbBackend>::migrations(
+                &TIMESCALE_MIGRATIONS,
+            )?);
+            #[cfg(feature = "sqlite")]
+            return Err("TimescaleDB migrations require PostgreSQL".into());
+        }
+        migrations.sort_unstable_by(|a, b| a.name().version().cmp(&b.name().version()));
+        Ok(migrations)
+    }
+}
+
+pub fn migrate_up(connection: &mut DbConnection, migrations: Migrations) -> Result {
+    let versions = connection
+        .run_pending_migrations(migrations)
+        .map_err(Error::Migration)?;
+
+    if versions.is_empty() {
+        info!("Database is up to date");
+    }
+    for version in versions {
+        info!("Applied migration {}", version);
+    }
+    Ok(())
+}
+
+pub fn migrate_down(connection: &mut DbConnection, migrations: Migrations) -> Result {
+    let version = connection
+        .revert_last_migration(migrations)
+        .map_err(Error::Migration)?;
+    info!("Reverted migration {}", version);
+    Ok(())
+}
+
+pub fn migration_status(
+    connection: &mut DbConnection,
+    migrations: Migrations,
+) -> Result<Vec<(String, bool)>> {
+    let applied = connection.applied_migrations().map_err(Error::Migration)?;
+
+    Ok(migrations
+        .migrations()
+        .map_err(Error::Migration)?
+        .iter()
+        .map(|migration| {
+            let name = migration.name();
+            (name.to_string(), applied.contains(&name.version()))
+        })
+        .collect())
+}
+
+#[cfg(all(test, not(feature = "sqlite")))]
+mod tests {
+    use super::{DbBackend, Migrations, MIGRATIONS, TIMESCALE_MIGRATIONS};
+    use diesel::migration::MigrationSource;
+
+    #[test]
+    fn apply_timescale_migrations_last() {
+        let plain = MigrationSource::<DbBackend>::migrations(&MIGRATIONS).unwrap();
+        let timescale = MigrationSource::<DbBackend>::migrations(&TIMESCALE_MIGRATIONS).unwrap();
+        let combined = Migrations { timescale: true }.migrations().unwrap();
+
+        let names: Vec<String> = combined
+            .iter()
+            .map(|migration| migration.name().to_string())
+            .collect();
+        let expected: Vec<String> = plain
+            .iter()
+            .chain(&timescale)
+            .map(|migration| migration.name().to_string())
+            .collect();
+        assert_eq!(names, expected);
+    }
+}
@@ -0,0 +1,968 @@
+use crate::binance::{
+    interval_to_millis, millis_to_interval, FundingRate, Kline, MarketEndpoint, OpenInterestSummary,
+};
+use crate::database::{DbBackend, DbConnection};
+use crate::result::Result;
+use crate::schema::{binance_funding_rates, binance_klines, binance_open_interest_summaries};
+use arrow::array::{ArrayRef, Decimal128Array, Int64Array, StringArray, TimestampMillisecondArray};
+use arrow::compute::kernels::cast_utils::parse_decimal;
+use arrow::datatypes::{DataType, Decimal128Type, Field, Schema, SchemaRef, TimeUnit};
+use arrow::record_batch::RecordBatch;
+use chrono::{DateTime, Datelike, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use log::info;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use serde::Serialize;
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+const BATCH_SIZE: i64 = 10_000;
+const DECIMAL_PRECISION: u8 = 38;
+const DECIMAL_SCALE: i8 = 18;
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ArgEnum)]
+pub enum Dataset {
+    Klines,
+    OpenInterest,
+    Funding,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct Filter {
+    /// Only rows of this market (klines only)
+    #[clap(short, long, arg_enum, value_parser)]
+    pub market: Option<MarketEndpoint>,
+
+    /// Only rows of these symbols
+    #[clap(short, long, value_parser)]
+    pub symbol: Vec<String>,
+
+    /// Only rows of this interval (klines and open interest)
+    #[clap(short, long, value_parser)]
+    pub interval: Option<String>,
+
+    /// Start time
+    #[clap(long = "from", value_parser)]
+    pub start_time: Option<DateTime<Utc>>,
+
+    /// End time
+    #[clap(long = "to", value_parser)]
+    pub end_time: Option<DateTime<Utc>>,
+}
+
+fn kline_query(
+    filter: &Filter,
+    interval: Option<i64>,
+) -> binance_klines::BoxedQuery<'_, DbBackend> {
+    let mut query = binance_klines::table.into_boxed();
+    if let Some(market) = filter.market {
+        query = query.filter(binance_klines::source.eq(market));
+    }
+    if !filter.symbol.is_empty() {
+        query = query.filter(binance_klines::symbol.eq_any(&filter.symbol));
+    }
+    if let Some(interval) = interval {
+        query =
+            query.filter((binance_klines::close_time - binance_klines::open_time).eq(interval - 1));
+    }
+    if let Some(start_time) = filter.start_time {
+        query = query.filter(binance_klines::open_time.ge(start_time.timestamp_millis()));
+    }
+    if let Some(end_time) = filter.end_time {
+        query = query.filter(binance_klines::open_time.le(end_time.timestamp_millis()));
+    }
+    query
+}
+
+/// A row with the times it was first and last stored, which exports carry
+/// like the views.
+#[derive(Debug, Clone)]
+pub struct Stored<T> {
+    pub row: T,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+impl<T> From<(T, NaiveDateTime, NaiveDateTime)> for Stored<T> {
+    fn from((row, created_at, updated_at): (T, NaiveDateTime, NaiveDateTime)) -> Self {
+        Self {
+            row,
+            created_at,
+            updated_at,
+        }
+    }
+}
+
+/// Pages through the klines matching `filter` series by series in time
+/// order, selecting each page with `load`.
+fn each_kline_page<'a, T, F>(
+    connection: &mut DbConnection,
+    filter: &'a Filter,
+    load: impl Fn(binance_klines::BoxedQuery<'a, DbBackend>, &mut DbConnection) -> QueryResult<Vec<T>>,
+    kline: impl Fn(&T) -> &Kline,
+    mut f: F,
+) -> Result
+where
+    F: FnMut(&[T]) -> Result,
+{
+    let interval = filter
+        .interval
+        .as_deref()
+        .map(interval_to_millis)
+        .transpose()?;
+    let series: Vec<(MarketEndpoint, String)> = kline_query(filter, interval)
+        .select((binance_klines::source, binance_klines::symbol))
+        .distinct()
+        .order((binance_klines::source, binance_klines::symbol))
+        .load(connection)?;
+
+    for (source, symbol) in series {
+        let mut last: Option<(i64, i64)> = None;
+        loop {
+            let mut query = kline_query(filter, interval)
+                .filter(binance_klines::source.eq(source))
+                .filter(binance_klines::symbol.eq(symbol.to_owned()));
+            if let Some((open_time, close_time)) = last {
+                query = query.filter(
+                    binance_klines::open_time
+                        .gt(open_time)
+                        .or(binance_klines::open_time
+                            .eq(open_time)
+                            .and(binance_klines::close_time.gt(close_time))),
+                );
+            }
+            let query = query
+                .order((binance_klines::open_time, binance_klines::close_time))
+                .limit(BATCH_SIZE);
+            let rows = load(query, connection)?;
+
+            let Some(row) = rows.last() else {
+                break;
+            };
+            last = Some((kline(row).open_time, kline(row).close_time));
+            f(&rows)?;
+            if (rows.len() as i64) < BATCH_SIZE {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Walks the klines matching `filter` series by series in time order, a batch
+/// at a time, so exports never hold a whole table in memory.
+pub fn each_kline_batch<F>(connection: &mut DbConnection, filter: &Filter, f: F) -> Result
+where
+    F: FnMut(&[Kline]) -> Result,
+{
+    each_kline_page(
+        connection,
+        filter,
+        |query, connection| query.select(Kline::as_select()).load(connection),
+        |kline| kline,
+        f,
+    )
+}
+
+/// Same as `each_kline_batch` with the times each kline was stored.
+pub fn each_stored_kline_batch<F>(connection: &mut DbConnection, filter: &Filter, f: F) -> Result
+where
+    F: FnMut(&[Stored<Kline>]) -> Result,
+{
+    each_kline_page(
+        connection,
+        filter,
+        |query, connection| {
+            let rows: Vec<(Kline, NaiveDateTime, NaiveDateTime)> = query
+                .select((
+                    Kline::as_select(),
+                    binance_klines::created_at,
+                    binance_klines::updated_at,
+                ))
+                .load(connection)?;
+            Ok(rows.into_iter().map(Stored::from).collect())
+        },
+        |stored| &stored.row,
+        f,
+    )
+}
+
+fn open_interest_query(
+    filter: &Filter,
+) -> binance_open_interest_summaries::BoxedQuery<'_, DbBackend> {
+    let mut query = binance_open_interest_summaries::table.into_boxed();
+    if !filter.symbol.is_empty() {
+        query = query.filter(binance_open_interest_summaries::symbol.eq_any(&filter.symbol));
+    }
+    if let Some(interval) = &filter.interval {
+        query = query.filter(binance_open_interest_summaries::interval.eq(interval));
+    }
+    if let Some(start_time) = filter.start_time {
+        query = query
+            .filter(binance_open_interest_summaries::timestamp.ge(start_time.timestamp_millis()));
+    }
+    if let Some(end_time) = filter.end_time {
+        query = query
+            .filter(binance_open_interest_summaries::timestamp.le(end_time.timestamp_millis()));
+    }
+    query
+}
+
+/// Same as `each_stored_kline_batch` for open interest summaries.
+pub fn each_open_interest_batch<F>(
+    connection: &mut DbConnection,
+    filter: &Filter,
+    mut f: F,
+) -> Result
+where
+    F: FnMut(&[Stored<OpenInterestSummary>]) -> Result,
+{
+    let series: Vec<(String, String)> = open_interest_query(filter)
+        .select((
+            binance_open_interest_summaries::symbol,
+            binance_open_interest_summaries::interval,
+        ))
+        .distinct()
+        .order((
+            binance_open_interest_summaries::symbol,
+            binance_open_interest_summaries::interval,
+        ))
+        .load(connection)?;
+
+    for (symbol, interval) in series {
+        let mut last: Option<i64> = None;
+        loop {
+            let mut query = open_interest_query(filter)
+                .filter(binance_open_interest_summaries::symbol.eq(&symbol))
+                .filter(binance_open_interest_summaries::interval.eq(&interval));
+            if let Some(timestamp) = last {
+                query = query.filter(binance_open_interest_summaries::timestamp.gt(timestamp));
+            }
+            let summaries: Vec<Stored<OpenInterestSummary>> = query
+                .select((
+                    OpenInterestSummary::as_select(),
+                    binance_open_interest_summaries::created_at,
+                    binance_open_interest_summaries::updated_at,
+                ))
+                .order(binance_open_interest_summaries::timestamp)
+                .limit(BATCH_SIZE)
+                .load::<(OpenInterestSummary, NaiveDateTime, NaiveDateTime)>(connection)?
+                .into_iter()
+                .map(Stored::from)
+                .collect();
+
+            let Some(summary) = summaries.last() else {
+                break;
+            };
+            last = Some(summary.row.timestamp);
+            f(&summaries)?;
+            if (summaries.len() as i64) < BATCH_SIZE {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn funding_query(filter: &Filter) -> binance_funding_rates::BoxedQuery<'_, DbBackend> {
+    let mut query = binance_funding_rates::table.into_boxed();
+    if !filter.symbol.is_empty() {
+        query = query.filter(binance_funding_rates::symbol.eq_any(&filter.symbol));
+    }
+    if let Some(start_time) = filter.start_time {
+        query = query.filter(binance_funding_rates::funding_time.ge(start_time.timestamp_millis()));
+    }
+    if let Some(end_time) = filter.end_time {
+        query = query.filter(binance_funding_rates::funding_time.le(end_time.timestamp_millis()));
+    }
+    query
+}
+
+/// Same as `each_kline_batch` for funding rates.
+pub fn each_funding_batch<F>(connection: &mut DbConnection, filter: &Filter, mut f: F) -> Result
+where
+    F: FnMut(&[FundingRate]) -> Result,
+{
+    let symbols: Vec<String> = funding_query(filter)
+        .select(binance_funding_rates::symbol)
+        .distinct()
+        .order(binance_funding_rates::symbol)
+        .load(connection)?;
+
+    for symbol in symbols {
+        let mut last: Option<i64> = None;
+        loop {
+            let mut query = funding_query(filter).filter(binance_funding_rates::symbol.eq(&symbol));
+            if let Some(funding_time) = last {
+                query = query.filter(binance_funding_rates::funding_time.gt(funding_time));
+            }
+            let rates: Vec<FundingRate> = query
+                .select(FundingRate::as_select())
+                .order(binance_funding_rates::funding_time)
+                .limit(BATCH_SIZE)
+                .load(connection)?;
+
+            let Some(rate) = rates.last() else {
+                break;
+            };
+            last = Some(rate.funding_time);
+            f(&rates)?;
+            if (rates.len() as i64) < BATCH_SIZE {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn year(timestamp: i64) -> i32 {
+    DateTime::<Utc>::from_timestamp_millis(timestamp)
+        .map(|time| time.year())
+        .unwrap_or_default()
+}
+
+fn timestamps<T>(rows: &[T], timestamp: impl Fn(&T) -> i64) -> ArrayRef {
+    Arc::new(
+        TimestampMillisecondArray::from_iter_values(rows.iter().map(timestamp))
+            .with_timezone("UTC"),
+    )
+}
+
+fn decimals<T>(rows: &[T], value: impl Fn(&T) -> &str) -> Result<ArrayRef> {
+    let values = rows
+        .iter()
+        .map(|row| parse_decimal::<Decimal128Type>(value(row), DECIMAL_PRECISION, DECIMAL_SCALE))
+        .collect::<std::result::Result<Vec<i128>, _>>()?;
+    Ok(Arc::new(
+        Decimal128Array::from(values).with_precision_and_scale(DECIMAL_PRECISION, DECIMAL_SCALE)?,
+    ))
+}
+
+fn optional_decimals<T>(rows: &[T], value: impl Fn(&T) -> Option<&str>) -> Result<ArrayRef> {
+    let values = rows
+        .iter()
+        .map(|row| {
+            value(row)
+                .map(|value| {
+                    parse_decimal::<Decimal128Type>(value, DECIMAL_PRECISION, DECIMAL_SCALE)
+                })
+                .transpose()
+        })
+        .collect::<std::result::Result<Vec<Option<i128>>, _>>()?;
+    Ok(Arc::new(
+        Decimal128Array::from(values).with_precision_and_scale(DECIMAL_PRECISION, DECIMAL_SCALE)?,
+    ))
+}
+
+/// The `created_at` and `updated_at` columns of stored rows.
+fn stored_timestamps<T>(rows: &[Stored<T>]) -> [ArrayRef; 2] {
+    [
+        timestamps(rows, |row| row.created_at.and_utc().timestamp_millis()),
+        timestamps(rows, |row| row.updated_at.and_utc().timestamp_millis()),
+    ]
+}
+
+fn strings<T>(rows: &[T], value: impl Fn(&T) -> String) -> ArrayRef {
+    Arc::new(StringArray::from_iter_values(rows.iter().map(value)))
+}
+
+fn timestamp_field(name: &str) -> Field {
+    Field::new(
+        name,
+        DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+        false,
+    )
+}
+
+fn decimal_field(name: &str) -> Field {
+    Field::new(
+        name,
+        DataType::Decimal128(DECIMAL_PRECISION, DECIMAL_SCALE),
+        false,
+    )
+}
+
+/// Columns follow `binance_klines_view`.
+fn kline_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("symbol", DataType::Utf8, false),
+        Field::new("source", DataType::Utf8, false),
+        timestamp_field("open_time"),
+        Field::new("interval", DataType::Utf8, false),
+        decimal_field("open"),
+        decimal_field("high"),
+        decimal_field("low"),
+        decimal_field("close"),
+        decimal_field("base_volume"),
+        decimal_field("buy_base_volume"),
+        decimal_field("quote_volume"),
+        decimal_field("buy_quote_volume"),
+        Field::new("number_of_trades", DataType::Int64, false),
+        timestamp_field("created_at"),
+        timestamp_field("updated_at"),
+    ]))
+}
+
+fn kline_batch(schema: &SchemaRef, klines: &[Stored<Kline>]) -> Result<RecordBatch> {
+    let mut columns = vec![
+        strings(klines, |kline| kline.row.symbol.to_owned()),
+        strings(klines, |kline| kline.row.source.to_string()),
+        timestamps(klines, |kline| kline.row.open_time),
+        strings(klines, |kline| {
+            millis_to_interval(kline.row.close_time - kline.row.open_time + 1)
+        }),
+        decimals(klines, |kline| &kline.row.open)?,
+        decimals(klines, |kline| &kline.row.high)?,
+        decimals(klines, |kline| &kline.row.low)?,
+        decimals(klines, |kline| &kline.row.close)?,
+        decimals(klines, |kline| &kline.row.base_volume)?,
+        decimals(klines, |kline| &kline.row.buy_base_volume)?,
+        decimals(klines, |kline| &kline.row.quote_volume)?,
+        decimals(klines, |kline| &kline.row.buy_quote_volume)?,
+        Arc::new(Int64Array::from_iter_values(
+            klines.iter().map(|kline| kline.row.number_of_trades),
+        )),
+    ];
+    columns.extend(stored_timestamps(klines));
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+/// Columns follow `binance_open_interest_summaries_view`.
+fn open_interest_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("symbol", DataType::Utf8, false),
+        Field::new("interval", DataType::Utf8, false),
+        timestamp_field("timestamp"),
+        decimal_field("sum_open_interest"),
+        decimal_field("sum_open_interest_value"),
+        timestamp_field("created_at"),
+        timestamp_field("updated_at"),
+    ]))
+}
+
+fn open_interest_batch(
+    schema: &SchemaRef,
+    summaries: &[Stored<OpenInterestSummary>],
+) -> Result<RecordBatch> {
+    let mut columns = vec![
+        strings(summaries, |summary| summary.row.symbol.to_owned()),
+        strings(summaries, |summary| summary.row.interval.to_owned()),
+        timestamps(summaries, |summary| summary.row.timestamp),
+        decimals(summaries, |summary| &summary.row.sum_open_interest)?,
+        decimals(summaries, |summary| &summary.row.sum_open_interest_value)?,
+    ];
+    columns.extend(stored_timestamps(summaries));
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+fn funding_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("symbol", DataType::Utf8, false),
+        timestamp_field("funding_time"),
+        decimal_field("funding_rate"),
+        Field::new(
+            "mark_price",
+            DataType::Decimal128(DECIMAL_PRECISION, DECIMAL_SCALE),
+            true,
+        ),
+    ]))
+}
+
+fn funding_batch(schema: &SchemaRef, rates: &[FundingRate]) -> Result<RecordBatch> {
+    Ok(RecordBatch::try_new(
+        schema.clone(),
+        vec![
+            strings(rates, |rate| rate.symbol.to_owned()),
+            timestamps(rates, |rate| rate.funding_time),
+            decimals(rates, |rate| &rate.funding_rate)?,
+            optional_decimals(rates, |rate| rate.mark_price.as_deref())?,
+        ],
+    )?)
+}
+
+/// Writes batches into one file per Hive-style partition directory. Batches
+/// must arrive grouped by partition, which the ordered walks guarantee.
+struct PartitionedWriter {
+    root: PathBuf,
+    schema: SchemaRef,
+    current: Option<(PathBuf, ArrowWriter<File>)>,
+}
+
+impl PartitionedWriter {
+    fn new(root: &Path, schema: SchemaRef) -> Self {
+        Self {
+            root: root.to_owned(),
+            schema,
+            current: None,
+        }
+    }
+
+    fn write(&mut self, partition: PathBuf, batch: &RecordBatch) -> Result {
+        if !matches!(&self.current, Some((current, _)) if *current == partition) {
+            self.close()?;
+
+            let directory = self.root.join(&partition);
+            fs::create_dir_all(&directory)?;
+            let path = directory.join("data.parquet");
+            info!("Writing {}...", path.display());
+
+            let properties = WriterProperties::builder()
+                .set_compression(Compression::SNAPPY)
+                .build();
+            let writer =
+                ArrowWriter::try_new(File::create(path)?, self.schema.clone(), Some(properties))?;
+            self.current = Some((partition, writer));
+        }
+
+        if let Some((_, writer)) = &mut self.current {
+            writer.write(batch)?;
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result {
+        if let Some((_, writer)) = self.current.take() {
+            writer.close()?;
+        }
+        Ok(())
+    }
+}
+
+/// Exports a dataset into `source=…/symbol=…/year=…` (klines),
+/// `symbol=…/interval=…/year=…` (open interest) or `symbol=…/year=…`
+/// (funding) partitions under `output`.
+pub fn export_parquet(
+    connection: &mut DbConnection,
+    dataset: Dataset,
+    filter: &Filter,
+    output: &Path,
+) -> Result {
+    match dataset {
+        Dataset::Klines => {
+            let schema = kline_schema();
+            let mut writer = PartitionedWriter::new(output, schema.clone());
+            each_stored_kline_batch(connection, filter, |klines| {
+                for rows in klines.chunk_by(|a, b| year(a.row.open_time) == year(b.row.open_time)) {
+                    let kline = &rows[0].row;
+                    let partition = PathBuf::from(format!("source={}", kline.source))
+                        .join(format!("symbol={}", kline.symbol))
+                        .join(format!("year={}", year(kline.open_time)));
+                    writer.write(partition, &kline_batch(&schema, rows)?)?;
+                }
+                Ok(())
+            })?;
+            writer.close()
+        }
+
+        Dataset::OpenInterest => {
+            let schema = open_interest_schema();
+            let mut writer = PartitionedWriter::new(output, schema.clone());
+            each_open_interest_batch(connection, filter, |summaries| {
+                for rows in
+                    summaries.chunk_by(|a, b| year(a.row.timestamp) == year(b.row.timestamp))
+                {
+                    let summary = &rows[0].row;
+                    let partition = PathBuf::from(format!("symbol={}", summary.symbol))
+                        .join(format!("interval={}", summary.interval))
+                        .join(format!("year={}", year(summary.timestamp)));
+                    writer.write(partition, &open_interest_batch(&schema, rows)?)?;
+                }
+                Ok(())
+            })?;
+            writer.close()
+        }
+
+        Dataset::Funding => {
+            let schema = funding_schema();
+            let mut writer = PartitionedWriter::new(output, schema.clone());
+            each_funding_batch(connection, filter, |rates| {
+                for rows in rates.chunk_by(|a, b| year(a.funding_time) == year(b.funding_time)) {
+                    let partition = PathBuf::from(format!("symbol={}", rows[0].symbol))
+                        .join(format!("year={}", year(rows[0].funding_time)));
+                    writer.write(partition, &funding_batch(&schema, rows)?)?;
+                }
+                Ok(())
+            })?;
+            writer.close()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ArgEnum)]
+pub enum RowFormat {
+    /// Aligned columns, rows are held until the last one is known
+    Table,
+    Csv,
+    /// One JSON array
+    Json,
+    #[clap(name = "jsonl")]
+    JsonLines,
+}
+
+/// A kline as it appears in `binance_klines_view`, with decimals kept as the
+/// exact strings Binance sent.
+#[derive(Debug, Serialize)]
+struct KlineRow<'a> {
+    symbol: &'a str,
+    source: String,
+    open_time: DateTime<Utc>,
+    interval: String,
+    open: &'a str,
+    high: &'a str,
+    low: &'a str,
+    close: &'a str,
+    base_volume: &'a str,
+    buy_base_volume: &'a str,
+    quote_volume: &'a str,
+    buy_quote_volume: &'a str,
+    number_of_trades: i64,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl<'a> From<&'a Stored<Kline>> for KlineRow<'a> {
+    fn from(stored: &'a Stored<Kline>) -> Self {
+        let kline = &stored.row;
+        Self {
+            symbol: &kline.symbol,
+            source: kline.source.to_string(),
+            open_time: DateTime::<Utc>::from_timestamp_millis(kline.open_time).unwrap_or_default(),
+            interval: millis_to_interval(kline.close_time - kline.open_time + 1),
+            open: &kline.open,
+            high: &kline.high,
+            low: &kline.low,
+            close: &kline.close,
+            base_volume: &kline.base_volume,
+            buy_base_volume: &kline.buy_base_volume,
+            quote_volume: &kline.quote_volume,
+            buy_quote_volume: &kline.buy_quote_volume,
+            number_of_trades: kline.number_of_trades,
+            created_at: stored.created_at.and_utc(),
+            updated_at: stored.updated_at.and_utc(),
+        }
+    }
+}
+
+/// An open interest summary as it appears in
+/// `binance_open_interest_summaries_view`.
+#[derive(Debug, Serialize)]
+struct OpenInterestRow<'a> {
+    symbol: &'a str,
+    interval: &'a str,
+    timestamp: DateTime<Utc>,
+    sum_open_interest: &'a str,
+    sum_open_interest_value: &'a str,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl<'a> From<&'a Stored<OpenInterestSummary>> for OpenInterestRow<'a> {
+    fn from(stored: &'a Stored<OpenInterestSummary>) -> Self {
+        let summary = &stored.row;
+        Self {
+            symbol: &summary.symbol,
+            interval: &summary.interval,
+            timestamp: DateTime::<Utc>::from_timestamp_millis(summary.timestamp)
+                .unwrap_or_default(),
+            sum_open_interest: &summary.sum_open_interest,
+            sum_open_interest_value: &summary.sum_open_interest_value,
+            created_at: stored.created_at.and_utc(),
+            updated_at: stored.updated_at.and_utc(),
+        }
+    }
+}
+
+/// A funding rate, which has no view.
+#[derive(Debug, Serialize)]
+struct FundingRow<'a> {
+    symbol: &'a str,
+    funding_time: DateTime<Utc>,
+    funding_rate: &'a str,
+    mark_price: Option<&'a str>,
+}
+
+impl<'a> From<&'a FundingRate> for FundingRow<'a> {
+    fn from(rate: &'a FundingRate) -> Self {
+        Self {
+            symbol: &rate.symbol,
+            funding_time: DateTime::<Utc>::from_timestamp_millis(rate.funding_time)
+                .unwrap_or_default(),
+            funding_rate: &rate.funding_rate,
+            mark_price: rate.mark_price.as_deref(),
+        }
+    }
+}
+
+enum RowWriter {
+    /// Rows collected as CSV, aligned once complete
+    Table(Box<csv::Writer<Vec<u8>>>, Box<dyn Write>),
+    Csv(Box<csv::Writer<Box<dyn Write>>>),
+    Json(BufWriter<Box<dyn Write>>, usize),
+    JsonLines(BufWriter<Box<dyn Write>>),
+}
+
+impl RowWriter {
+    fn new(format: RowFormat, output: Option<&Path>) -> Result<Self> {
+        let output: Box<dyn Write> = match output {
+            Some(path) => Box::new(File::create(path)?),
+            None => Box::new(io::stdout().lock()),
+        };
+        Ok(Self::to(format, output))
+    }
+
+    fn to(format: RowFormat, output: Box<dyn Write>) -> Self {
+        match format {
+            RowFormat::Table => Self::Table(Box::new(csv::Writer::from_writer(Vec::new())), output),
+            RowFormat::Csv => Self::Csv(Box::new(csv::Writer::from_writer(output))),
+            RowFormat::Json => Self::Json(BufWriter::new(output), 0),
+            RowFormat::JsonLines => Self::JsonLines(BufWriter::new(output)),
+        }
+    }
+
+    fn write<T: Serialize>(&mut self, row: &T) -> Result {
+        match self {
+            Self::Table(writer, _) => writer.serialize(row)?,
+            Self::Csv(writer) => writer.serialize(row)?,
+            Self::Json(writer, count) => {
+                writer.write_all(if *count == 0 { b"[\n" } else { b",\n" })?;
+                serde_json::to_writer(&mut *writer, row)?;
+                *count += 1;
+            }
+            Self::JsonLines(writer) => {
+                serde_json::to_writer(&mut *writer, row)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result {
+        match self {
+            Self::Table(writer, output) => {
+                let rows =
+                    std::mem::replace(writer, Box::new(csv::Writer::from_writer(Vec::new())))
+                        .into_inner()
+                        .map_err(|error| io::Error::new(error.error().kind(), error.to_string()))?;
+                let mut reader = csv::Reader::from_reader(rows.as_slice());
+                let header = reader.headers()?.clone();
+                let rows = reader
+                    .records()
+                    .map(|record| Ok(record?.iter().map(str::to_owned).collect()))
+                    .collect::<Result<Vec<Vec<String>>>>()?;
+                write_table(output, &header.iter().collect::<Vec<_>>(), &rows)?;
+            }
+            Self::Csv(writer) => writer.flush()?,
+            Self::Json(writer, count) => {
+                writer.write_all(if *count == 0 { b"[]\n" } else { b"\n]\n" })?;
+                writer.flush()?;
+            }
+            Self::JsonLines(writer) => writer.flush()?,
+        }
+        Ok(())
+    }
+}
+
+/// Writes `rows` under `header` in left aligned columns.
+pub fn write_table<W>(output: &mut W, header: &[&str], rows: &[Vec<String>]) -> Result
+where
+    W: Write + ?Sized,
+{
+    let mut widths: Vec<usize> = header.iter().map(|name| name.len()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    let header: Vec<String> = header.iter().map(|name| name.to_string()).collect();
+    for row in std::iter::once(&header).chain(rows) {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        writeln!(output, "{}", cells.join("  ").trim_end())?;
+    }
+    Ok(())
+}
+
+/// Streams a dataset in `format` into `output`, or stdout if none.
+pub fn export_rows(
+    connection: &mut DbConnection,
+    dataset: Dataset,
+    filter: &Filter,
+    format: RowFormat,
+    output: Option<&Path>,
+) -> Result {
+    let mut writer = RowWriter::new(format, output)?;
+    match dataset {
+        Dataset::Klines => each_stored_kline_batch(connection, filter, |klines| {
+            klines
+                .iter()
+                .try_for_each(|kline| writer.write(&KlineRow::from(kline)))
+        })?,
+        Dataset::OpenInterest => each_open_interest_batch(connection, filter, |summaries| {
+            summaries
+                .iter()
+                .try_for_each(|summary| writer.write(&OpenInterestRow::from(summary)))
+        })?,
+        Dataset::Funding => each_funding_batch(connection, filter, |rates| {
+            rates
+                .iter()
+                .try_for_each(|rate| writer.write(&FundingRow::from(rate)))
+        })?,
+    }
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        kline_batch, kline_schema, KlineRow, OpenInterestRow, RowFormat, RowWriter, Stored,
+    };
+    use crate::binance::{Kline, MarketEndpoint, OpenInterestSummary};
+    use arrow::array::{Array, Decimal128Array, StringArray, TimestampMillisecondArray};
+    use chrono::NaiveDate;
+    use serde::Serialize;
+    use std::{
+        io::{self, Write},
+        sync::{Arc, Mutex},
+    };
+
+    fn stored<T>(row: T) -> Stored<T> {
+        let day = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        Stored {
+            row,
+            created_at: day.and_hms_opt(1, 0, 5).unwrap(),
+            updated_at: day.and_hms_opt(2, 0, 0).unwrap(),
+        }
+    }
+
+    fn kline() -> Stored<Kline> {
+        stored(Kline {
+            source: MarketEndpoint::USDM,
+            symbol: "BTCUSDT".into(),
+            open_time: 1704067200000,
+            close_time: 1704070799999,
+            open: "42283.58000000".into(),
+            high: "42554.57".into(),
+            low: "42261.02".into(),
+            close: "42475.23".into(),
+            base_volume: "1271.68108".into(),
+            quote_volume: "53957635.93826650".into(),
+            buy_base_volume: "682.69811".into(),
+            buy_quote_volume: "28966644.14120900".into(),
+            number_of_trades: 47134,
+            derived: false,
+        })
+    }
+
+    /// Output shared with the test once the writer owns it.
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn write_row<T: Serialize>(format: RowFormat, row: &T) -> String {
+        let output = Output::default();
+        let mut writer = RowWriter::to(format, Box::new(output.clone()));
+        writer.write(row).unwrap();
+        writer.flush().unwrap();
+        drop(writer);
+        let bytes = output.0.lock().unwrap().clone();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    fn write_kline(format: RowFormat) -> String {
+        write_row(format, &KlineRow::from(&kline()))
+    }
+
+    #[test]
+    fn write_kline_rows() {
+        assert_eq!(
+            write_kline(RowFormat::Csv),
+            "symbol,source,open_time,interval,open,high,low,close,base_volume,\
+             buy_base_volume,quote_volume,buy_quote_volume,number_of_trades,created_at,\
+             updated_at\n\
+             BTCUSDT,USDM,2024-01-01T00:00:00Z,1h,42283.58000000,42554.57,42261.02,\
+             42475.23,1271.68108,682.69811,53957635.93826650,28966644.14120900,47134,\
+             2024-01-01T01:00:05Z,2024-01-01T02:00:00Z\n"
+        );
+
+        let row = r#"{"symbol":"BTCUSDT","source":"USDM","open_time":"2024-01-01T00:00:00Z","interval":"1h","open":"42283.58000000","high":"42554.57","low":"42261.02","close":"42475.23","base_volume":"1271.68108","buy_base_volume":"682.69811","quote_volume":"53957635.93826650","buy_quote_volume":"28966644.14120900","number_of_trades":47134,"created_at":"2024-01-01T01:00:05Z","updated_at":"2024-01-01T02:00:00Z"}"#;
+        assert_eq!(write_kline(RowFormat::JsonLines), format!("{}\n", row));
+        assert_eq!(write_kline(RowFormat::Json), format!("[\n{}\n]\n", row));
+
+        let table = write_kline(RowFormat::Table);
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("symbol   source  open_time             interval  open"));
+        assert!(lines[0].ends_with("number_of_trades  created_at            updated_at"));
+        assert!(
+            lines[1].starts_with("BTCUSDT  USDM    2024-01-01T00:00:00Z  1h        42283.58000000")
+        );
+        assert!(lines[1].ends_with("47134             2024-01-01T01:00:05Z  2024-01-01T02:00:00Z"));
+    }
+
+    #[test]
+    fn write_open_interest_rows() {
+        let summary = stored(OpenInterestSummary {
+            symbol: "BTCUSDT".into(),
+            interval: "5m".into(),
+            timestamp: 1704067200000,
+            sum_open_interest: "80000.5".into(),
+            sum_open_interest_value: "3400000000.25".into(),
+        });
+        assert_eq!(
+            write_row(RowFormat::Csv, &OpenInterestRow::from(&summary)),
+            "symbol,interval,timestamp,sum_open_interest,sum_open_interest_value,created_at,\
+             updated_at\n\
+             BTCUSDT,5m,2024-01-01T00:00:00Z,80000.5,3400000000.25,2024-01-01T01:00:05Z,\
+             2024-01-01T02:00:00Z\n"
+        );
+    }
+
+    #[test]
+    fn convert_klines_to_record_batch() {
+        let batch = kline_batch(&kline_schema(), &[kline()]).unwrap();
+        let interval = batch
+            .column_by_name("interval")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        let open = batch
+            .column_by_name("open")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<Decimal128Array>()
+            .unwrap();
+
+        assert_eq!(batch.num_rows(), 1);
+        assert_eq!(interval.value(0), "1h");
+        let created_at = batch
+            .column_by_name("created_at")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<TimestampMillisecondArray>()
+            .unwrap();
+
+        assert_eq!(batch.num_rows(), 1);
+        assert_eq!(interval.value(0), "1h");
+        assert_eq!(open.value_as_string(0), "42283.580000000000000000");
+        assert_eq!(created_at.value(0), 1704070805000);
+    }
+}
@@ -0,0 +1,599 @@
+use crate::binance::{
+    interval_to_millis, FundingRate, KlineQuery, MarketEndpoint, OpenInterestSummary, StoreOptions,
+};
+use crate::database::DbPool;
+use crate::lock::Locks;
+use crate::rest::{BaseUrls, RestClient};
+use crate::result::{Error, Result};
+use crate::runs::{self, Run};
+use chrono::{DateTime, Utc};
+use futures_util::{stream, StreamExt};
+use log::{info, warn};
+use serde::Deserialize;
+use std::{
+    collections::HashSet,
+    fs,
+    future::Future,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// Binance's own default when a request carries no limit.
+const DEFAULT_LIMIT: u16 = 500;
+
+/// Jobs read from a TOML file of `[[job]]` tables.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Used when no `--database-url` is given
+    pub database_url: Option<String>,
+
+    /// Used when no `--api-base-url` or `--ws-base-url` is given
+    #[serde(default)]
+    pub spot: BaseUrls,
+
+    #[serde(default)]
+    pub usdm: BaseUrls,
+
+    #[serde(default, rename = "job")]
+    pub jobs: Vec<Job>,
+
+    /// Recorded with the runs of its jobs
+    #[serde(skip)]
+    pub hash: String,
+}
+
+impl Config {
+    pub fn from_path<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let contents = fs::read_to_string(&path)?;
+        let mut config: Self = toml::from_str(&contents)?;
+        config.hash = runs::config_hash(&contents);
+        let canonical = fs::canonicalize(&path)?;
+        for job in &mut config.jobs {
+            job.config = canonical.clone();
+        }
+        let mut names = HashSet::new();
+        for job in &config.jobs {
+            if !names.insert(&job.name) {
+                return Err(Error::Config(format!("duplicate job {}", job.name)));
+            }
+            job.validate()?;
+        }
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum JobDataset {
+    Kline,
+    OpenInterest,
+    Funding,
+}
+
+impl JobDataset {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Kline => "kline",
+            Self::OpenInterest => "open-interest",
+            Self::Funding => "funding",
+        }
+    }
+
+    /// Fetches `query` of the dataset into the database, `market` only
+    /// matters for klines.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn fetch(
+        self,
+        market: MarketEndpoint,
+        client: &RestClient,
+        query: &KlineQuery,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+        pool: &DbPool,
+        options: &StoreOptions,
+    ) -> Result {
+        match self {
+            Self::Kline => {
+                market
+                    .fetch(
+                        client, query, None, None, start_time, end_time, pool, options,
+                    )
+                    .await
+            }
+            Self::OpenInterest => {
+                OpenInterestSummary::fetch(
+                    client,
+                    query,
+                    None,
+                    Some(query.limit),
+                    start_time,
+                    end_time,
+                    pool,
+                    &options.run_id,
+                )
+                .await
+            }
+            Self::Funding => {
+                FundingRate::fetch(
+                    client,
+                    &query.symbol,
+                    Some(query.limit),
+                    start_time,
+                    end_time,
+                    pool,
+                    &options.run_id,
+                )
+                .await
+            }
+        }
+    }
+}
+
+impl FromStr for JobDataset {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "kline" => Ok(Self::Kline),
+            "open-interest" => Ok(Self::OpenInterest),
+            "funding" => Ok(Self::Funding),
+            _ => Err(Error::ParseStr(s.to_owned())),
+        }
+    }
+}
+
+/// When a job runs: `every 15m` for a fixed interval, anything else is a cron
+/// expression with a leading seconds field such as `0 */5 * * * *`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Schedule {
+    Every(i64),
+    Cron(Box<cron::Schedule>),
+}
+
+impl Schedule {
+    /// First run after `time`, fixed intervals are aligned to the epoch like
+    /// kline buckets.
+    pub fn next_after(&self, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Self::Every(interval) => {
+                let millis = time.timestamp_millis();
+                DateTime::from_timestamp_millis(millis - millis.rem_euclid(*interval) + interval)
+            }
+            Self::Cron(cron) => cron.after(&time).next(),
+        }
+    }
+}
+
+impl TryFrom<String> for Schedule {
+    type Error = Error;
+
+    fn try_from(schedule: String) -> Result<Self> {
+        match schedule.strip_prefix("every ") {
+            Some(interval) => match interval_to_millis(interval.trim())? {
+                interval if interval > 0 => Ok(Self::Every(interval)),
+                _ => Err(Error::Config(format!(
+                    "schedule {} is not positive",
+                    schedule
+                ))),
+            },
+            None => cron::Schedule::from_str(&schedule)
+                .map(|cron| Self::Cron(Box::new(cron)))
+                .map_err(|_| Error::ParseStr(schedule)),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Job {
+    pub name: String,
+
+    pub dataset: JobDataset,
+
+    /// Required for klines, open interest and funding only exist on USDM
+    pub market: Option<MarketEndpoint>,
+
+    #[serde(default)]
+    pub symbols: Vec<String>,
+
+    /// Adds every symbol trading against this asset, such as `USDT`
+    pub quote_asset: Option<String>,
+
+    /// Adds the rows of a `symbol,interval,limit` CSV file
+    pub csv: Option<PathBuf>,
+
+    /// Replace the intervals of CSV rows
+    #[serde(default)]
+    pub intervals: Vec<String>,
+
+    /// Replaces the limits of CSV rows
+    pub limit: Option<u16>,
+
+    pub from: Option<DateTime<Utc>>,
+
+    pub to: Option<DateTime<Utc>>,
+
+    /// Start this long before now when `from` is not given, such as `2h`
+    pub lookback: Option<String>,
+
+    /// When `beholder daemon` runs the job, `beholder run` runs it once
+    pub schedule: Option<Schedule>,
+
+    /// Quarantine fetched klines breaking a validation rule instead of
+    /// storing them
+    #[serde(default)]
+    pub validate: bool,
+
+    /// Record changes of stored klines in `binance_kline_revisions`
+    #[serde(default)]
+    pub audit: bool,
+
+    /// Delay each scheduled run by up to this long, such as `30s`
+    pub jitter: Option<String>,
+
+    /// The config file defining the job, made absolute so runs from other
+    /// directories match
+    #[serde(skip)]
+    pub config: PathBuf,
+}
+
+#[derive(Debug, Default)]
+pub struct JobReport {
+    pub queries: usize,
+    pub failed: Vec<(String, Error)>,
+    /// Another run of the job held its lock
+    pub skipped: bool,
+}
+
+impl Job {
+    fn validate(&self) -> Result {
+        let error = |message: &str| Err(Error::Config(format!("job {} {}", self.name, message)));
+        match (self.dataset, self.market) {
+            (JobDataset::Kline, None) => return error("needs a market"),
+            (JobDataset::OpenInterest | JobDataset::Funding, Some(MarketEndpoint::Spot)) => {
+                return error("is only available on usdm")
+            }
+            _ => (),
+        }
+        if self.symbols.is_empty() && self.quote_asset.is_none() && self.csv.is_none() {
+            return error("selects no symbols");
+        }
+        if self.dataset != JobDataset::Funding && self.intervals.is_empty() && self.csv.is_none() {
+            return error("needs intervals");
+        }
+        if self.from.is_some() && self.lookback.is_some() {
+            return error("has both from and lookback");
+        }
+        for interval in self.intervals.iter().chain(&self.lookback) {
+            if interval_to_millis(interval)? <= 0 {
+                return error(&format!(
+                    "has an interval {} that is not positive",
+                    interval
+                ));
+            }
+        }
+        if let Some(jitter) = &self.jitter {
+            if interval_to_millis(jitter)? < 0 {
+                return error(&format!("has a negative jitter {}", jitter));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn market(&self) -> MarketEndpoint {
+        self.market.unwrap_or(MarketEndpoint::USDM)
+    }
+
+    /// Runs with the same identity share an advisory lock, jobs of the same
+    /// name in other config files do not.
+    fn identity(&self) -> String {
+        format!(
+            "job {} {:?} {} {}",
+            self.name,
+            self.dataset,
+            self.market(),
+            self.config.display()
+        )
+    }
+
+    /// Expands symbols, selectors and CSV rows into one query per symbol and
+    /// interval, funding queries carry no interval.
+    pub async fn queries(&self, client: &RestClient) -> Result<Vec<KlineQuery>> {
+        let mut rows = match &self.csv {
+            Some(path) => KlineQuery::from_csv(path)?,
+            None => Vec::new(),
+        };
+        let mut symbols = self.symbols.clone();
+        if let Some(quote_asset) = &self.quote_asset {
+            symbols.extend(self.market().symbols_quoted_in(client, quote_asset).await?);
+        }
+        rows.extend(symbols.into_iter().map(|symbol| KlineQuery {
+            symbol,
+            interval: String::new(),
+            limit: DEFAULT_LIMIT,
+        }));
+
+        let mut queries: Vec<KlineQuery> = Vec::new();
+        for row in rows {
+            let limit = self.limit.unwrap_or(row.limit);
+            let intervals = match self.dataset {
+                JobDataset::Funding => vec![String::new()],
+                _ if self.intervals.is_empty() => vec![row.interval],
+                _ => self.intervals.clone(),
+            };
+            for interval in intervals {
+                let query = KlineQuery {
+                    symbol: row.symbol.to_owned(),
+                    interval,
+                    limit,
+                };
+                if !queries.contains(&query) {
+                    queries.push(query);
+                }
+            }
+        }
+        Ok(queries)
+    }
+
+    /// Start and end time in milliseconds, `lookback` counts back from `now`.
+    pub fn time_range(&self, now: DateTime<Utc>) -> Result<(Option<u64>, Option<u64>)> {
+        let start_time = match (&self.from, &self.lookback) {
+            (Some(from), _) => Some(from.timestamp_millis()),
+            (None, Some(lookback)) => Some(now.timestamp_millis() - interval_to_millis(lookback)?),
+            (None, None) => None,
+        };
+        let end_time = self.to.map(|to| to.timestamp_millis());
+        Ok((start_time.map(|t| t as u64), end_time.map(|t| t as u64)))
+    }
+
+    /// Fetches every query of the job, up to `concurrency` at once, recorded
+    /// as a run of `command` in `beholder_runs`. `locks` decide what happens
+    /// while another run of the job is in progress.
+    pub async fn run(
+        &self,
+        command: &str,
+        config_hash: &str,
+        locks: &Locks,
+        pool: &DbPool,
+        client: &RestClient,
+        concurrency: usize,
+    ) -> Result<JobReport> {
+        let Some(_lock) = locks.lock(&self.identity()).await? else {
+            return Ok(JobReport {
+                skipped: true,
+                ..JobReport::default()
+            });
+        };
+        let run = Run::new(command, self.name.to_owned(), Some(config_hash.to_owned()));
+        run.start(pool).await?;
+        let report = self.fetch(pool, client, concurrency, &run.id).await;
+        match &report {
+            Ok(report) => run.finish(pool, &report.failed, None).await?,
+            Err(error) => run.finish(pool, &[], Some(error)).await?,
+        }
+        report
+    }
+
+    async fn fetch(
+        &self,
+        pool: &DbPool,
+        client: &RestClient,
+        concurrency: usize,
+        run_id: &str,
+    ) -> Result<JobReport> {
+        let (start_time, end_time) = self.time_range(Utc::now())?;
+        let queries = self.queries(client).await?;
+        info!("Running job {} with {} queries", self.name, queries.len());
+        let options = &StoreOptions {
+            validate: self.validate,
+            audit: self.audit,
+            run_id: run_id.to_owned(),
+        };
+
+        let failed = fetch_all(&queries, concurrency, |query| {
+            self.dataset.fetch(
+                self.market(),
+                client,
+                query,
+                start_time,
+                end_time,
+                pool,
+                options,
+            )
+        })
+        .await?;
+        Ok(JobReport {
+            queries: queries.len(),
+            failed,
+            skipped: false,
+        })
+    }
+}
+
+/// Runs `fetch` for every query with up to `concurrency` in flight. Failed
+/// requests only fail their query, any other error cancels the rest.
+pub async fn fetch_all<'a, F, R>(
+    queries: &'a [KlineQuery],
+    concurrency: usize,
+    fetch: F,
+) -> Result<Vec<(String, Error)>>
+where
+    F: Fn(&'a KlineQuery) -> R,
+    R: Future<Output = Result>,
+{
+    let fetch = &fetch;
+    let mut results = stream::iter(queries)
+        .map(|query| async move { (query, fetch(query).await) })
+        .buffer_unordered(concurrency.max(1));
+
+    let mut failed = Vec::new();
+    while let Some((query, result)) = results.next().await {
+        match result {
+            Ok(()) => (),
+            Err(error) if error.is_remote() => {
+                warn!("Fetching {} failed: {}", query, error);
+                failed.push((query.to_string(), error));
+            }
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, JobDataset, Schedule};
+    use crate::binance::{KlineQuery, MarketEndpoint};
+    use crate::rest::RestClient;
+    use chrono::{TimeZone, Utc};
+
+    #[tokio::test]
+    async fn load_jobs_from_toml() {
+        let config = Config::from_path("tests/assets/jobs.toml").unwrap();
+        let client = RestClient::default();
+        assert_eq!(config.jobs.len(), 3);
+        assert_eq!(
+            config.spot.api_base_url.as_deref(),
+            Some("https://testnet.binance.vision")
+        );
+        assert_eq!(config.usdm.api_base_url, None);
+
+        let job = &config.jobs[0];
+        assert_eq!(
+            job.identity(),
+            format!(
+                "job usdm-klines Kline USDM {}",
+                std::fs::canonicalize("tests/assets/jobs.toml")
+                    .unwrap()
+                    .display()
+            )
+        );
+        assert_eq!(job.dataset, JobDataset::Kline);
+        assert_eq!(job.market, Some(MarketEndpoint::USDM));
+        assert!(matches!(job.schedule, Some(Schedule::Every(60_000))));
+        assert_eq!(
+            job.queries(&client).await.unwrap(),
+            vec![
+                KlineQuery {
+                    symbol: "BTCUSDT".into(),
+                    interval: "1m".into(),
+                    limit: 1000,
+                },
+                KlineQuery {
+                    symbol: "BTCUSDT".into(),
+                    interval: "1h".into(),
+                    limit: 1000,
+                },
+                KlineQuery {
+                    symbol: "ETHUSDT".into(),
+                    interval: "1m".into(),
+                    limit: 1000,
+                },
+                KlineQuery {
+                    symbol: "ETHUSDT".into(),
+                    interval: "1h".into(),
+                    limit: 1000,
+                },
+            ]
+        );
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 2, 0, 0).unwrap();
+        assert_eq!(
+            job.time_range(now).unwrap(),
+            (Some(1_704_067_200_000), None)
+        );
+
+        // CSV rows keep their own intervals and limits
+        let job = &config.jobs[1];
+        assert_eq!(
+            job.queries(&client).await.unwrap(),
+            KlineQuery::from_csv("tests/assets/kline_queries_1.csv").unwrap()
+        );
+        assert!(matches!(job.schedule, Some(Schedule::Cron(_))));
+
+        let job = &config.jobs[2];
+        assert_eq!(job.dataset, JobDataset::Funding);
+        assert_eq!(job.queries(&client).await.unwrap().len(), 1);
+    }
+
+    #[test]
+    fn schedule_next_run() {
+        let time = Utc.with_ymd_and_hms(2024, 1, 1, 0, 7, 30).unwrap();
+
+        let every = Schedule::try_from("every 15m".to_owned()).unwrap();
+        assert_eq!(
+            every.next_after(time),
+            Some(Utc.with_ymd_and_hms(2024, 1, 1, 0, 15, 0).unwrap())
+        );
+
+        let cron = Schedule::try_from("0 5 0 * * *".to_owned()).unwrap();
+        assert_eq!(
+            cron.next_after(time),
+            Some(Utc.with_ymd_and_hms(2024, 1, 2, 0, 5, 0).unwrap())
+        );
+
+        for schedule in ["every 0m", "every -5s"] {
+            assert!(matches!(
+                Schedule::try_from(schedule.to_owned()),
+                Err(crate::result::Error::Config(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn isolate_failed_queries() {
+        use super::fetch_all;
+        use crate::result::Error;
+
+        let queries: Vec<_> = (0..20)
+            .map(|i| KlineQuery {
+                symbol: format!("SYMBOL{}", i),
+                interval: "1h".into(),
+                limit: 10,
+            })
+            .collect();
+
+        let failed = fetch_all(&queries, 4, |query| async move {
+            match query.symbol.len() {
+                7 => Err(Error::Api(400, "-1121 Invalid symbol.".into())),
+                _ => Ok(()),
+            }
+        })
+        .await
+        .unwrap();
+        let mut failed: Vec<_> = failed.into_iter().map(|(query, _)| query).collect();
+        failed.sort();
+        assert_eq!(failed.len(), 10);
+        assert_eq!(failed[0], "SYMBOL0@1h");
+
+        let result = fetch_all(&queries, 4, |_| async {
+            Err(Error::Config("database is gone".into()))
+        })
+        .await;
+        assert!(matches!(result, Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn reject_invalid_jobs() {
+        let invalid = [
+            "[[job]]\nname = \"a\"\ndataset = \"kline\"\nsymbols = [\"BTCUSDT\"]\nintervals = [\"1m\"]",
+            "[[job]]\nname = \"a\"\ndataset = \"funding\"\nmarket = \"spot\"\nsymbols = [\"BTCUSDT\"]",
+            "[[job]]\nname = \"a\"\ndataset = \"open-interest\"\nsymbols = [\"BTCUSDT\"]",
+            "[[job]]\nname = \"a\"\ndataset = \"funding\"\nsymbols = [\"BTCUSDT\"]\nschedule = \"every day\"",
+            "[[job]]\nname = \"a\"\ndataset = \"funding\"\nsymbols = [\"BTCUSDT\"]\njitter = \"-5s\"",
+            "[[job]]\nname = \"a\"\ndataset = \"funding\"\nsymbols = [\"BTCUSDT\"]\nlookback = \"0h\"",
+            "[[job]]\nname = \"a\"\ndataset = \"funding\"\nsymbols = [\"BTCUSDT\"]\nlookback = \"-2h\"",
+            "[[job]]\nname = \"a\"\ndataset = \"kline\"\nmarket = \"usdm\"\nsymbols = [\"BTCUSDT\"]\nintervals = [\"0m\"]",
+        ];
+        for config in invalid {
+            let result = toml::from_str::<Config>(config)
+                .map_err(Into::into)
+                .and_then(|config| config.jobs.iter().try_for_each(|job| job.validate()));
+            assert!(result.is_err(), "{}", config);
+        }
+    }
+}
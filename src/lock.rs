@@ -0,0 +1,123 @@
+use crate::result::Result;
+
+/// What a run does when another run of the same job holds its lock.
+#[derive(Debug, Clone, Copy, PartialEq, clap::ArgEnum)]
+pub enum LockMode {
+    Skip,
+    Wait,
+    Fail,
+}
+
+/// Options of the advisory lock keeping runs of one job from overlapping.
+#[derive(Debug, clap::Args)]
+pub struct LockOptions {
+    /// Skip, wait or fail when another run of the same job is in progress,
+    /// only PostgreSQL databases are locked
+    #[clap(long, arg_enum, value_parser, default_value = "skip")]
+    pub on_locked: LockMode,
+}
+
+impl LockOptions {
+    pub fn locks(&self, database_url: &str) -> Locks {
+        Locks {
+            database_url: database_url.to_owned(),
+            mode: self.on_locked,
+        }
+    }
+}
+
+/// Takes the advisory locks of runs on connections of their own, so a held
+/// lock never takes a connection from the pool its run fetches with.
+#[derive(Debug, Clone)]
+pub struct Locks {
+    database_url: String,
+    mode: LockMode,
+}
+
+impl Locks {
+    /// Takes the advisory lock of `job` for as long as the returned guard
+    /// lives. Returns `None` when the lock is held and the mode skips, fails
+    /// with `Error::Locked` when the mode fails.
+    pub async fn lock(&self, job: &str) -> Result<Option<JobLock>> {
+        lock(&self.database_url, job, self.mode).await
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+mod postgres {
+    use super::LockMode;
+    use crate::database::DbConnection;
+    use crate::result::{Error, Result};
+    use diesel::prelude::*;
+    use diesel::sql_types::BigInt;
+    use log::{info, warn};
+    use sha2::{Digest, Sha256};
+
+    sql_function!(fn pg_try_advisory_lock(key: BigInt) -> Bool);
+    sql_function!(fn pg_advisory_unlock(key: BigInt) -> Bool);
+
+    /// Advisory locks are keyed by numbers, derived here from the job.
+    fn key(job: &str) -> i64 {
+        let digest = Sha256::digest(job.as_bytes());
+        i64::from_be_bytes(digest[..8].try_into().unwrap())
+    }
+
+    /// The advisory lock of a job, held by its own connection until
+    /// dropped.
+    pub struct JobLock {
+        connection: DbConnection,
+        key: i64,
+    }
+
+    impl Drop for JobLock {
+        fn drop(&mut self) {
+            if let Err(error) =
+                diesel::select(pg_advisory_unlock(self.key)).execute(&mut self.connection)
+            {
+                warn!("Cannot release advisory lock {}: {}", self.key, error);
+            }
+        }
+    }
+
+    pub async fn lock(database_url: &str, job: &str, mode: LockMode) -> Result<Option<JobLock>> {
+        let key = key(job);
+        let database_url = database_url.to_owned();
+        let name = job.to_owned();
+        let connection = tokio::task::spawn_blocking(move || -> Result<_> {
+            let mut connection = DbConnection::establish(&database_url)?;
+            let locked = diesel::select(pg_try_advisory_lock(key)).get_result(&mut connection)?;
+            if locked {
+                return Ok(Some(connection));
+            }
+            if mode != LockMode::Wait {
+                return Ok(None);
+            }
+            info!("Waiting for another run of {}", name);
+            diesel::sql_query("SELECT pg_advisory_lock($1)")
+                .bind::<BigInt, _>(key)
+                .execute(&mut connection)?;
+            Ok(Some(connection))
+        })
+        .await
+        .expect("Database task panicked")?;
+
+        match connection {
+            Some(connection) => Ok(Some(JobLock { connection, key })),
+            None if mode == LockMode::Fail => Err(Error::Locked(job.to_owned())),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+use postgres::{lock, JobLock};
+
+/// SQLite has no advisory locks, its writers are serialized by the database
+/// file instead.
+#[cfg(feature = "sqlite")]
+pub struct JobLock;
+
+#[cfg(feature = "sqlite")]
+async fn lock(_database_url: &str, _job: &str, _mode: LockMode) -> Result<Option<JobLock>> {
+    Ok(Some(JobLock))
+}
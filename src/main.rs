@@ -2,17 +2,43 @@ extern crate binance as binance_client;
 #[macro_use]
 extern crate diesel;
 
+mod audit;
+mod bars;
 mod binance;
+mod binance_vision;
+mod daemon;
+mod database;
+mod export;
+mod jobs;
+mod lock;
+mod queue;
+mod rate_limit;
+mod recording;
+mod resample;
+mod rest;
 mod result;
+mod retry;
+mod runs;
 mod schema;
+mod status;
+mod validate;
 
-use crate::binance::{KlineQuery, MarketEndpoint};
+use crate::binance::{KlineQuery, MarketEndpoint, StoreOptions};
+use crate::database::{DbConnection, DbPool, Migrations};
+use crate::export::{Dataset, Filter, RowFormat};
+use crate::lock::LockOptions;
+use crate::queue::WorkerOptions;
+use crate::recording::Recording;
+use crate::rest::{BaseUrls, ClientOptions, RestClient};
+use crate::runs::Run;
+use crate::status::StatusFormat;
+use crate::validate::Rule;
 use chrono::{DateTime, Utc};
 use clap::{Parser, Subcommand};
-use diesel::pg::PgConnection;
 use diesel::prelude::*;
-use log::warn;
-use result::Error;
+use log::{info, warn};
+use result::{Error, Result};
+use std::path::PathBuf;
 
 fn main() {
     let cli = Cli::parse();
@@ -21,7 +47,11 @@ fn main() {
 
 #[derive(Parser)]
 #[clap(author, version, about)]
-#[clap(propagate_version = true, subcommand_required = true, arg_required_else_help = true)]
+#[clap(
+    propagate_version = true,
+    subcommand_required = true,
+    arg_required_else_help = true
+)]
 struct Cli {
     /// Silence all output
     #[clap(short, long, action)]
@@ -35,6 +65,16 @@ struct Cli {
     #[clap(short, long, arg_enum, value_parser)]
     timestamp: Option<stderrlog::Timestamp>,
 
+    /// Save every response and stream frame received from Binance into this
+    /// directory
+    #[clap(long, value_parser, conflicts_with = "replay-dir")]
+    record_dir: Option<PathBuf>,
+
+    /// Serve responses and stream frames saved by `--record-dir` instead of
+    /// reaching Binance
+    #[clap(long, value_parser)]
+    replay_dir: Option<PathBuf>,
+
     #[clap(subcommand)]
     command: Commands,
 }
@@ -49,7 +89,12 @@ impl Cli {
             .init()
             .unwrap();
 
-        self.command.run();
+        let recording = match (self.record_dir, self.replay_dir) {
+            (Some(dir), _) => Some(Recording::record(dir).unwrap()),
+            (_, Some(dir)) => Some(Recording::replay(dir).unwrap()),
+            _ => None,
+        };
+        self.command.run(recording);
     }
 }
 
@@ -61,21 +106,822 @@ enum Commands {
         #[clap(short, long, value_parser)]
         database_url: String,
 
+        /// Apply pending migrations before snapshot
+        #[clap(long, action)]
+        auto_migrate: bool,
+
+        #[clap(flatten)]
+        client: ClientOptions,
+
+        #[clap(flatten)]
+        lock: LockOptions,
+
+        /// Symbols fetched in parallel, each on its own database connection
+        #[clap(long, value_parser, default_value_t = 1)]
+        concurrency: usize,
+
         /// Snapshot data to database
         #[clap(subcommand)]
         command: SnapshotCommands,
     },
+
+    /// Export stored data to files
+    Export {
+        /// The database to read
+        #[clap(short, long, value_parser)]
+        database_url: String,
+
+        /// Export format
+        #[clap(subcommand)]
+        command: ExportCommands,
+    },
+
+    /// Print stored rows with decimals and timestamps decoded
+    Query {
+        /// The database to read
+        #[clap(short, long, value_parser)]
+        database_url: String,
+
+        /// Dataset to read
+        #[clap(arg_enum, value_parser)]
+        dataset: Dataset,
+
+        /// Output format
+        #[clap(long, arg_enum, value_parser, default_value = "table")]
+        format: RowFormat,
+
+        #[clap(flatten)]
+        filter: Filter,
+    },
+
+    /// Import data from files
+    Import {
+        /// The database to store
+        #[clap(short, long, value_parser)]
+        database_url: String,
+
+        /// Import source
+        #[clap(subcommand)]
+        command: ImportCommands,
+    },
+
+    /// Aggregate stored klines into a higher interval
+    Resample {
+        /// The database to resample in
+        #[clap(short, long, value_parser)]
+        database_url: String,
+
+        /// Only resample this market
+        #[clap(short, long, arg_enum, value_parser)]
+        market: Option<MarketEndpoint>,
+
+        /// Only resample these symbols
+        #[clap(short, long, value_parser)]
+        symbol: Vec<String>,
+
+        /// Interval of the stored klines to aggregate
+        #[clap(long, value_parser, default_value = "1m")]
+        from_interval: String,
+
+        /// Interval to produce
+        #[clap(short, long, value_parser)]
+        interval: String,
+
+        /// Start time
+        #[clap(long = "from", value_parser)]
+        start_time: Option<DateTime<Utc>>,
+
+        /// End time
+        #[clap(long = "to", value_parser)]
+        end_time: Option<DateTime<Utc>>,
+
+        /// Also write buckets missing some of their klines
+        #[clap(long, action)]
+        include_incomplete: bool,
+
+        /// Build `1d` or `1w` sessions of this IANA timezone into
+        /// `binance_local_klines`, e.g. `Asia/Shanghai`
+        #[clap(long, value_parser)]
+        timezone: Option<String>,
+
+        /// Local time sessions open at, requires `--timezone`
+        #[clap(long, value_parser, default_value = "00:00", requires = "timezone")]
+        session_start: String,
+    },
+
+    /// Build time, volume, dollar or tick bars from stored aggregated trades
+    Bars {
+        /// The database holding the trades
+        #[clap(short, long, value_parser)]
+        database_url: String,
+
+        /// Only build bars of this market
+        #[clap(short, long, arg_enum, value_parser)]
+        market: Option<MarketEndpoint>,
+
+        /// Only build bars of these symbols
+        #[clap(short, long, value_parser)]
+        symbol: Vec<String>,
+
+        /// Bar type
+        #[clap(long = "type", arg_enum, value_parser)]
+        bar_type: bars::BarType,
+
+        /// Interval of time bars such as `45m`, or the base volume, quote
+        /// volume or number of aggregated trades closing other bars
+        #[clap(long, value_parser)]
+        size: String,
+
+        /// Delete and rebuild existing bars instead of resuming after them
+        #[clap(long, action)]
+        rebuild: bool,
+    },
+
+    /// Run the jobs of a TOML config once
+    Run {
+        /// The database to store, overrides `database_url` of the config
+        #[clap(short, long, value_parser)]
+        database_url: Option<String>,
+
+        /// The TOML file of jobs
+        #[clap(short, long, value_parser)]
+        config: PathBuf,
+
+        /// Only run these jobs
+        #[clap(short, long, value_parser)]
+        job: Vec<String>,
+
+        /// Print the queries and next scheduled run of each job instead
+        #[clap(long, action)]
+        list: bool,
+
+        #[clap(flatten)]
+        client: ClientOptions,
+
+        #[clap(flatten)]
+        lock: LockOptions,
+
+        /// Symbols fetched in parallel, each on its own database connection
+        #[clap(long, value_parser, default_value_t = 1)]
+        concurrency: usize,
+    },
+
+    /// Run the jobs of a TOML config on their schedules
+    Daemon {
+        /// The database to store, overrides `database_url` of the config
+        #[clap(short, long, value_parser)]
+        database_url: Option<String>,
+
+        /// The TOML file of jobs
+        #[clap(short, long, value_parser)]
+        config: PathBuf,
+
+        /// Only run these jobs
+        #[clap(short, long, value_parser)]
+        job: Vec<String>,
+
+        /// Database connections shared by all jobs
+        #[clap(long, value_parser, default_value_t = 4)]
+        pool_size: u32,
+
+        #[clap(flatten)]
+        client: ClientOptions,
+
+        #[clap(flatten)]
+        lock: LockOptions,
+
+        /// Symbols fetched in parallel, each on its own database connection
+        #[clap(long, value_parser, default_value_t = 1)]
+        concurrency: usize,
+    },
+
+    /// Split the jobs of a TOML config into tasks for `beholder worker`
+    Enqueue {
+        /// The database of the queue, overrides `database_url` of the config
+        #[clap(short, long, value_parser)]
+        database_url: Option<String>,
+
+        /// The TOML file of jobs
+        #[clap(short, long, value_parser)]
+        config: PathBuf,
+
+        /// Only enqueue these jobs
+        #[clap(short, long, value_parser)]
+        job: Vec<String>,
+
+        /// Time range of each task, such as `1d`
+        #[clap(long, value_parser, default_value = "1d")]
+        chunk: String,
+
+        /// Runs of a task before it is given up
+        #[clap(long, value_parser, default_value_t = 3)]
+        max_attempts: i32,
+
+        #[clap(flatten)]
+        client: ClientOptions,
+    },
+
+    /// Claim and run tasks queued by `beholder enqueue`
+    Worker {
+        /// The database of the queue
+        #[clap(short, long, value_parser)]
+        database_url: String,
+
+        #[clap(flatten)]
+        client: ClientOptions,
+
+        #[clap(flatten)]
+        options: WorkerOptions,
+    },
+
+    /// Check stored klines against data quality rules
+    Validate {
+        /// The database to check
+        #[clap(short, long, value_parser)]
+        database_url: String,
+
+        /// Rules to check, all if omitted
+        #[clap(long, arg_enum, value_parser)]
+        rule: Vec<Rule>,
+
+        /// Move violating klines into binance_klines_quarantine instead of
+        /// failing
+        #[clap(long, action)]
+        quarantine: bool,
+
+        #[clap(flatten)]
+        filter: Filter,
+    },
+
+    /// Report coverage and freshness of stored klines and open interest
+    Status {
+        /// The database to inspect
+        #[clap(short, long, value_parser)]
+        database_url: String,
+
+        /// Output format
+        #[clap(long, arg_enum, value_parser, default_value = "table")]
+        format: StatusFormat,
+
+        /// Exit with an error if the newest row of a series is older than
+        /// this interval, such as `2h`
+        #[clap(long, value_parser)]
+        stale_after: Option<String>,
+    },
+
+    /// Manage database schema
+    Migrate {
+        /// The database to migrate
+        #[clap(short, long, value_parser)]
+        database_url: String,
+
+        /// Include TimescaleDB hypertables, compression and rollups
+        #[clap(long, action)]
+        timescale: bool,
+
+        /// Migration action
+        #[clap(subcommand)]
+        command: MigrateCommands,
+    },
 }
 
 impl Commands {
-    fn run(self) {
+    fn run(self, recording: Option<Recording>) {
         match self {
             Self::Snapshot {
                 database_url,
+                auto_migrate,
+                client,
+                lock,
+                concurrency,
+                command,
+            } => {
+                let pool = database::pool(&database_url, (concurrency as u32).max(1)).unwrap();
+                if auto_migrate {
+                    database::migrate_up(&mut pool.get().unwrap(), Migrations::default()).unwrap();
+                }
+                let client = client.client(&BaseUrls::default(), &BaseUrls::default(), recording);
+                let run = Run::new(
+                    command.name(),
+                    runs::arguments(std::env::args().skip(1)),
+                    None,
+                );
+                let identity = command.identity();
+                let locks = lock.locks(&database_url);
+                let failed = runtime()
+                    .block_on(async {
+                        let lock = locks.lock(&identity).await?;
+                        if lock.is_none() {
+                            return Ok(None);
+                        }
+                        run.start(&pool).await?;
+                        let result = command.run(&pool, &client, concurrency, &run.id).await;
+                        match &result {
+                            Ok(failed) => run.finish(&pool, failed, None).await?,
+                            Err(error) => run.finish(&pool, &[], Some(error)).await?,
+                        }
+                        result.map(Some)
+                    })
+                    .unwrap();
+                let Some(failed) = failed else {
+                    info!("Skipped, another run of {} holds its lock", identity);
+                    return;
+                };
+                if !failed.is_empty() {
+                    warn!("{} queries failed permanently:", failed.len());
+                    for (query, error) in &failed {
+                        warn!("  {}: {}", query, error);
+                    }
+                    std::process::exit(1);
+                }
+            }
+
+            Self::Export {
+                database_url,
+                command,
+            } => {
+                let mut connection = DbConnection::establish(&database_url).unwrap();
+                command.run(&mut connection).unwrap();
+            }
+
+            Self::Query {
+                database_url,
+                dataset,
+                format,
+                filter,
+            } => {
+                let mut connection = DbConnection::establish(&database_url).unwrap();
+                export::export_rows(&mut connection, dataset, &filter, format, None).unwrap();
+            }
+
+            Self::Import {
+                database_url,
+                command,
+            } => {
+                let mut connection = DbConnection::establish(&database_url).unwrap();
+                command.run(&mut connection).unwrap();
+            }
+
+            Self::Resample {
+                database_url,
+                market,
+                symbol,
+                from_interval,
+                interval,
+                start_time,
+                end_time,
+                include_incomplete,
+                timezone,
+                session_start,
+            } => {
+                let mut connection = DbConnection::establish(&database_url).unwrap();
+                let filter = Filter {
+                    market,
+                    symbol,
+                    interval: Some(from_interval),
+                    start_time,
+                    end_time,
+                };
+                let report = match timezone {
+                    Some(timezone) => {
+                        let session =
+                            resample::Session::new(&timezone, &session_start, &interval).unwrap();
+                        resample::resample_local(
+                            &mut connection,
+                            &filter,
+                            &session,
+                            include_incomplete,
+                        )
+                    }
+                    None => {
+                        resample::resample(&mut connection, &filter, &interval, include_incomplete)
+                    }
+                }
+                .unwrap();
+                for bucket in &report.incomplete {
+                    println!(
+                        "{} {} {} {}/{}",
+                        bucket.source,
+                        bucket.symbol,
+                        bucket.open_time,
+                        bucket.count,
+                        bucket.expected
+                    );
+                }
+                info!(
+                    "Wrote {} {} klines, {} incomplete buckets",
+                    report.written,
+                    interval,
+                    report.incomplete.len()
+                );
+            }
+
+            Self::Bars {
+                database_url,
+                market,
+                symbol,
+                bar_type,
+                size,
+                rebuild,
+            } => {
+                let mut connection = DbConnection::establish(&database_url).unwrap();
+                let spec = bars::BarSpec::new(bar_type, &size).unwrap();
+                let written =
+                    bars::build_bars(&mut connection, market, &symbol, &spec, rebuild).unwrap();
+                info!("Wrote {} bars", written);
+            }
+
+            Self::Run {
+                database_url,
+                config,
+                job,
+                list,
+                client,
+                lock,
+                concurrency,
+            } => {
+                let config = jobs::Config::from_path(config).unwrap();
+                let client = client.client(&config.spot, &config.usdm, recording);
+                let runtime = runtime();
+                if list {
+                    let now = Utc::now();
+                    for config_job in &config.jobs {
+                        let next = config_job
+                            .schedule
+                            .as_ref()
+                            .and_then(|schedule| schedule.next_after(now))
+                            .map_or("unscheduled".to_owned(), |time| time.to_rfc3339());
+                        println!("{} {}", config_job.name, next);
+                        for query in runtime.block_on(config_job.queries(&client)).unwrap() {
+                            println!("  {} {} {}", query.symbol, query.interval, query.limit);
+                        }
+                    }
+                    return;
+                }
+                let database_url = database_url
+                    .or(config.database_url)
+                    .expect("No database URL in arguments or config");
+                let pool = database::pool(&database_url, (concurrency as u32).max(1)).unwrap();
+
+                let mut failed = 0;
+                for config_job in config.jobs {
+                    if !job.is_empty() && !job.contains(&config_job.name) {
+                        continue;
+                    }
+                    let report = runtime
+                        .block_on(config_job.run(
+                            "run",
+                            &config.hash,
+                            &lock.locks(&database_url),
+                            &pool,
+                            &client,
+                            concurrency,
+                        ))
+                        .unwrap();
+                    if report.skipped {
+                        info!(
+                            "Job {} skipped, another run holds its lock",
+                            config_job.name
+                        );
+                        continue;
+                    }
+                    for (query, error) in &report.failed {
+                        warn!("Job {} failed on {}: {}", config_job.name, query, error);
+                    }
+                    info!(
+                        "Job {} ran {} queries, {} failed",
+                        config_job.name,
+                        report.queries,
+                        report.failed.len()
+                    );
+                    failed += report.failed.len();
+                }
+                if failed > 0 {
+                    std::process::exit(1);
+                }
+            }
+
+            Self::Daemon {
+                database_url,
+                config,
+                job,
+                pool_size,
+                client,
+                lock,
+                concurrency,
+            } => {
+                let mut config = jobs::Config::from_path(config).unwrap();
+                if !job.is_empty() {
+                    config
+                        .jobs
+                        .retain(|config_job| job.contains(&config_job.name));
+                }
+                let database_url = database_url
+                    .or(config.database_url)
+                    .expect("No database URL in arguments or config");
+                let pool = database::pool(&database_url, pool_size).unwrap();
+                let client = client.client(&config.spot, &config.usdm, recording);
+
+                runtime()
+                    .block_on(daemon::run(
+                        &config.jobs,
+                        &config.hash,
+                        &lock.locks(&database_url),
+                        &pool,
+                        &client,
+                        concurrency,
+                    ))
+                    .unwrap();
+            }
+
+            Self::Enqueue {
+                database_url,
+                config,
+                job,
+                chunk,
+                max_attempts,
+                client,
+            } => {
+                let config = jobs::Config::from_path(config).unwrap();
+                let client = client.client(&config.spot, &config.usdm, recording);
+                let database_url = database_url
+                    .or(config.database_url)
+                    .expect("No database URL in arguments or config");
+                let pool = database::pool(&database_url, 1).unwrap();
+                let chunk = binance::interval_to_millis(&chunk).unwrap();
+
+                let runtime = runtime();
+                for config_job in config.jobs {
+                    if !job.is_empty() && !job.contains(&config_job.name) {
+                        continue;
+                    }
+                    let added = runtime
+                        .block_on(queue::enqueue(
+                            &config_job,
+                            &client,
+                            &pool,
+                            chunk,
+                            max_attempts,
+                        ))
+                        .unwrap();
+                    info!("Queued {} tasks of job {}", added, config_job.name);
+                }
+            }
+
+            Self::Worker {
+                database_url,
+                client,
+                options,
+            } => {
+                let pool =
+                    database::pool(&database_url, (options.concurrency as u32).max(1)).unwrap();
+                let client = client.client(&BaseUrls::default(), &BaseUrls::default(), recording);
+                let run = Run::new("worker", runs::arguments(std::env::args().skip(1)), None);
+                let failed = runtime()
+                    .block_on(async {
+                        run.start(&pool).await?;
+                        let result = queue::work(&pool, &client, &options, &run.id).await;
+                        match &result {
+                            Ok(failed) => run.finish(&pool, failed, None).await?,
+                            Err(error) => run.finish(&pool, &[], Some(error)).await?,
+                        }
+                        result
+                    })
+                    .unwrap();
+                if !failed.is_empty() {
+                    warn!("{} tasks failed permanently:", failed.len());
+                    for (task, error) in &failed {
+                        warn!("  {}: {}", task, error);
+                    }
+                    std::process::exit(1);
+                }
+            }
+
+            Self::Validate {
+                database_url,
+                rule,
+                quarantine,
+                filter,
+            } => {
+                let rules = if rule.is_empty() {
+                    Rule::ALL.to_vec()
+                } else {
+                    rule
+                };
+                let mut connection = DbConnection::establish(&database_url).unwrap();
+                let violations = validate::validate(&mut connection, &filter, &rules).unwrap();
+                if quarantine {
+                    validate::move_to_quarantine(&violations, &mut connection).unwrap();
+                    info!("Quarantined {} klines", violations.len());
+                } else if !violations.is_empty() {
+                    validate::print_violations(&violations).unwrap();
+                    warn!("{} klines break a rule", violations.len());
+                    std::process::exit(1);
+                }
+            }
+
+            Self::Status {
+                database_url,
+                format,
+                stale_after,
+            } => {
+                let stale_after = stale_after
+                    .as_deref()
+                    .map(binance::interval_to_millis)
+                    .transpose()
+                    .unwrap();
+                let mut connection = DbConnection::establish(&database_url).unwrap();
+                let statuses = status::status(&mut connection, Utc::now()).unwrap();
+                status::print_status(&statuses, format).unwrap();
+
+                if let Some(stale_after) = stale_after {
+                    let stale: Vec<_> = statuses
+                        .iter()
+                        .filter(|status| status.age * 1_000 > stale_after)
+                        .collect();
+                    for status in &stale {
+                        warn!(
+                            "{} {} {} {} is stale, last updated {}s ago",
+                            status.dataset,
+                            status.source,
+                            status.symbol,
+                            status.interval,
+                            status.age
+                        );
+                    }
+                    if !stale.is_empty() {
+                        std::process::exit(1);
+                    }
+                }
+            }
+
+            Self::Migrate {
+                database_url,
+                timescale,
                 command,
             } => {
-                let mut connection = PgConnection::establish(&database_url).unwrap();
-                command.run(&mut connection);
+                let mut connection = DbConnection::establish(&database_url).unwrap();
+                command
+                    .run(&mut connection, Migrations { timescale })
+                    .unwrap();
+            }
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum ExportCommands {
+    /// Write Parquet files partitioned by source, symbol and year
+    Parquet {
+        /// Dataset to export
+        #[clap(long, arg_enum, value_parser)]
+        dataset: Dataset,
+
+        /// Directory to write partitions into
+        #[clap(short, long, value_parser)]
+        output: PathBuf,
+
+        #[clap(flatten)]
+        filter: Filter,
+    },
+
+    /// Stream rows as CSV
+    Csv {
+        /// Dataset to export
+        #[clap(long, arg_enum, value_parser)]
+        dataset: Dataset,
+
+        /// File to write, stdout if omitted
+        #[clap(short, long, value_parser)]
+        output: Option<PathBuf>,
+
+        #[clap(flatten)]
+        filter: Filter,
+    },
+
+    /// Stream rows as JSON Lines
+    Jsonl {
+        /// Dataset to export
+        #[clap(long, arg_enum, value_parser)]
+        dataset: Dataset,
+
+        /// File to write, stdout if omitted
+        #[clap(short, long, value_parser)]
+        output: Option<PathBuf>,
+
+        #[clap(flatten)]
+        filter: Filter,
+    },
+}
+
+impl ExportCommands {
+    fn run(self, connection: &mut DbConnection) -> Result {
+        match self {
+            Self::Parquet {
+                dataset,
+                output,
+                filter,
+            } => export::export_parquet(connection, dataset, &filter, &output),
+
+            Self::Csv {
+                dataset,
+                output,
+                filter,
+            } => export::export_rows(
+                connection,
+                dataset,
+                &filter,
+                RowFormat::Csv,
+                output.as_deref(),
+            ),
+
+            Self::Jsonl {
+                dataset,
+                output,
+                filter,
+            } => export::export_rows(
+                connection,
+                dataset,
+                &filter,
+                RowFormat::JsonLines,
+                output.as_deref(),
+            ),
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum ImportCommands {
+    /// Import kline and aggTrades archives from data.binance.vision
+    BinanceVision {
+        /// Archives, or directories searched for archives
+        #[clap(value_parser, required = true)]
+        paths: Vec<PathBuf>,
+
+        /// Market of the archives, inferred from their paths if omitted
+        #[clap(short, long, arg_enum, value_parser)]
+        market: Option<MarketEndpoint>,
+
+        /// Skip verification against .CHECKSUM files
+        #[clap(long, action)]
+        no_verify: bool,
+
+        #[clap(flatten)]
+        store: StoreOptions,
+    },
+}
+
+impl ImportCommands {
+    fn run(self, connection: &mut DbConnection) -> Result {
+        match self {
+            Self::BinanceVision {
+                paths,
+                market,
+                no_verify,
+                store,
+            } => {
+                let summary =
+                    binance_vision::import(connection, &paths, market, !no_verify, &store)?;
+                info!(
+                    "Imported {} klines and {} trades from {} archives, skipped {}, failed {}",
+                    summary.klines,
+                    summary.trades,
+                    summary.imported.len(),
+                    summary.skipped.len(),
+                    summary.failed.len()
+                );
+                match summary.failed.into_iter().next() {
+                    Some((_, error)) => Err(error),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum MigrateCommands {
+    /// Apply all pending migrations
+    Up,
+
+    /// Revert the last applied migration
+    Down,
+
+    /// List migrations and whether they are applied
+    Status,
+}
+
+impl MigrateCommands {
+    fn run(self, connection: &mut DbConnection, migrations: Migrations) -> Result {
+        match self {
+            Self::Up => database::migrate_up(connection, migrations),
+            Self::Down => database::migrate_down(connection, migrations),
+            Self::Status => {
+                for (name, applied) in database::migration_status(connection, migrations)? {
+                    let state = if applied { "applied" } else { "pending" };
+                    println!("{} {}", state, name);
+                }
+                Ok(())
             }
         }
     }
@@ -91,9 +937,30 @@ enum SnapshotCommands {
 }
 
 impl SnapshotCommands {
-    fn run(self, connection: &mut PgConnection) {
+    /// Recorded as the command of the run in `beholder_runs`.
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Binance { command } => command.name(),
+        }
+    }
+
+    /// Snapshots of the same identity share an advisory lock.
+    fn identity(&self) -> String {
+        match self {
+            Self::Binance { command } => command.identity(),
+        }
+    }
+
+    /// Returns the queries that failed even after retries.
+    async fn run(
+        self,
+        pool: &DbPool,
+        client: &RestClient,
+        concurrency: usize,
+        run_id: &str,
+    ) -> Result<Vec<(String, Error)>> {
         match self {
-            Self::Binance { command } => command.run(connection),
+            Self::Binance { command } => command.run(pool, client, concurrency, run_id).await,
         }
     }
 }
@@ -125,6 +992,9 @@ enum BinanceCommands {
         /// End time
         #[clap(long = "to", value_parser)]
         end_time: Option<DateTime<Utc>>,
+
+        #[clap(flatten)]
+        store: StoreOptions,
     },
 
     /// Watch Klines in real time
@@ -140,6 +1010,9 @@ enum BinanceCommands {
         /// Use the interval instead of interval in CSV
         #[clap(short, long, value_parser)]
         interval: Option<String>,
+
+        #[clap(flatten)]
+        store: StoreOptions,
     },
 
     /// Fetch open interest summaries
@@ -167,7 +1040,35 @@ enum BinanceCommands {
 }
 
 impl BinanceCommands {
-    fn run(self, connection: &mut PgConnection) {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Kline { .. } => "snapshot binance kline",
+            Self::KlineStream { .. } => "snapshot binance kline-stream",
+            Self::OpenInterestSummary { .. } => "snapshot binance open-interest-summary",
+        }
+    }
+
+    /// The command, market and CSV file, whose path is made absolute so
+    /// snapshots from other directories match.
+    fn identity(&self) -> String {
+        let path = |csv: &String| {
+            std::fs::canonicalize(csv).map_or(csv.to_owned(), |path| path.display().to_string())
+        };
+        match self {
+            Self::Kline { market, csv, .. } | Self::KlineStream { market, csv, .. } => {
+                format!("{} {} {}", self.name(), market, path(csv))
+            }
+            Self::OpenInterestSummary { csv, .. } => format!("{} {}", self.name(), path(csv)),
+        }
+    }
+
+    async fn run(
+        self,
+        pool: &DbPool,
+        client: &RestClient,
+        concurrency: usize,
+        run_id: &str,
+    ) -> Result<Vec<(String, Error)>> {
         match self {
             Self::Kline {
                 market,
@@ -176,36 +1077,40 @@ impl BinanceCommands {
                 limit,
                 start_time,
                 end_time,
+                mut store,
             } => {
-                let queries = KlineQuery::from_csv(csv).unwrap();
+                store.run_id = run_id.to_owned();
+                let queries = KlineQuery::from_csv(csv)?;
+                let queries = with_interval(queries, interval);
 
-                for query in queries {
-                    match market.fetch(
-                        &query,
-                        interval.to_owned(),
+                jobs::fetch_all(&queries, concurrency, |query| {
+                    market.fetch(
+                        client,
+                        query,
+                        None,
                         limit,
                         start_time.map(|t| t.timestamp_millis() as u64),
                         end_time.map(|t| t.timestamp_millis() as u64),
-                        connection,
-                    ) {
-                        Ok(()) => (),
-                        Err(Error::BinanceClient(error)) => {
-                            warn!("Binance client failed: {}", error);
-                            continue;
-                        }
-                        error => error.unwrap(),
-                    }
-                }
+                        pool,
+                        &store,
+                    )
+                })
+                .await
             }
 
             Self::KlineStream {
                 market,
                 csv,
                 interval,
+                mut store,
             } => {
-                let queries = KlineQuery::from_csv(csv).unwrap();
+                store.run_id = run_id.to_owned();
+                let queries = KlineQuery::from_csv(csv)?;
 
-                market.watch(&queries, interval, connection);
+                market
+                    .watch(client, &queries, interval, pool, &store)
+                    .await?;
+                Ok(Vec::new())
             }
 
             Self::OpenInterestSummary {
@@ -215,26 +1120,43 @@ impl BinanceCommands {
                 end_time,
                 limit,
             } => {
-                let queries = KlineQuery::from_csv(csv).unwrap();
+                let queries = KlineQuery::from_csv(csv)?;
+                let queries = with_interval(queries, interval);
 
-                for query in queries {
-                    match binance::OpenInterestSummary::fetch(
-                        &query,
-                        interval.to_owned(),
+                jobs::fetch_all(&queries, concurrency, |query| {
+                    binance::OpenInterestSummary::fetch(
+                        client,
+                        query,
+                        None,
                         limit,
                         start_time.map(|t| t.timestamp_millis() as u64),
                         end_time.map(|t| t.timestamp_millis() as u64),
-                        connection,
-                    ) {
-                        Ok(()) => (),
-                        Err(Error::BinanceClient(error)) => {
-                            warn!("Binance client failed: {}", error);
-                            continue;
-                        }
-                        error => error.unwrap(),
-                    }
-                }
+                        pool,
+                        run_id,
+                    )
+                })
+                .await
             }
         }
     }
 }
+
+/// The runtime of commands talking to Binance, database queries run on its
+/// blocking threads.
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Runtime::new().expect("Cannot start the async runtime")
+}
+
+/// Replaces the intervals of CSV rows with `interval` when given.
+fn with_interval(queries: Vec<KlineQuery>, interval: Option<String>) -> Vec<KlineQuery> {
+    match interval {
+        Some(interval) => queries
+            .into_iter()
+            .map(|query| KlineQuery {
+                interval: interval.to_owned(),
+                ..query
+            })
+            .collect(),
+        None => queries,
+    }
+}
@@ -0,0 +1,420 @@
+use crate::binance::{interval_to_millis, KlineQuery, MarketEndpoint, StoreOptions};
+use crate::database::{with_connection, DbConnection, DbPool};
+use crate::jobs::{Job, JobDataset};
+use crate::rest::RestClient;
+use crate::result::{Error, Result};
+use crate::schema::beholder_tasks;
+use chrono::{NaiveDateTime, TimeDelta, Utc};
+use diesel::prelude::*;
+use futures_util::future;
+use log::{info, warn};
+use std::{fmt, time::Duration};
+
+const PENDING: &str = "pending";
+const RUNNING: &str = "running";
+const SUCCEEDED: &str = "succeeded";
+const FAILED: &str = "failed";
+
+/// Options of `beholder worker`.
+#[derive(Debug, clap::Args)]
+pub struct WorkerOptions {
+    /// Tasks run at once, each on its own database connection
+    #[clap(long, value_parser, default_value_t = 1)]
+    pub concurrency: usize,
+
+    /// How long a claimed task stays with this worker before others may
+    /// retry it, such as `10m`
+    #[clap(long, value_parser, default_value = "10m")]
+    pub lease: String,
+
+    /// Delay before retrying a failed task, multiplied by its attempts
+    #[clap(long, value_parser, default_value = "1m")]
+    pub retry_delay: String,
+
+    /// Wait this long when no task is ready
+    #[clap(long, value_parser, default_value = "10s")]
+    pub poll_interval: String,
+
+    /// Stop once every task succeeded or used up its attempts
+    #[clap(long, action)]
+    pub exit_when_done: bool,
+}
+
+/// A task split from a job, fetching one symbol and interval over one chunk
+/// of time.
+#[derive(Debug, Insertable)]
+#[diesel(table_name = beholder_tasks)]
+struct NewTask {
+    job: String,
+    dataset: &'static str,
+    source: MarketEndpoint,
+    symbol: String,
+    interval: String,
+    row_limit: i32,
+    start_time: i64,
+    end_time: i64,
+    validate: bool,
+    audit: bool,
+    max_attempts: i32,
+    available_at: NaiveDateTime,
+}
+
+#[derive(Debug, Queryable, Selectable)]
+#[diesel(table_name = beholder_tasks)]
+struct Task {
+    id: i64,
+    dataset: String,
+    source: MarketEndpoint,
+    symbol: String,
+    interval: String,
+    row_limit: i32,
+    start_time: i64,
+    end_time: i64,
+    validate: bool,
+    audit: bool,
+    attempts: i32,
+    max_attempts: i32,
+}
+
+impl fmt::Display for Task {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "task {} {} {}@{} {}..{}",
+            self.id, self.dataset, self.symbol, self.interval, self.start_time, self.end_time
+        )
+    }
+}
+
+impl Task {
+    /// Fetches the task through the same code as `beholder run`.
+    async fn execute(&self, client: &RestClient, pool: &DbPool, run_id: &str) -> Result {
+        let query = KlineQuery {
+            symbol: self.symbol.to_owned(),
+            interval: self.interval.to_owned(),
+            limit: u16::try_from(self.row_limit)?,
+        };
+        let options = StoreOptions {
+            validate: self.validate,
+            audit: self.audit,
+            run_id: run_id.to_owned(),
+        };
+        self.dataset
+            .parse::<JobDataset>()?
+            .fetch(
+                self.source,
+                client,
+                &query,
+                Some(self.start_time as u64),
+                Some(self.end_time as u64),
+                pool,
+                &options,
+            )
+            .await
+    }
+}
+
+/// Splits `start_time..=end_time` into ranges of up to `chunk` milliseconds.
+fn chunks(start_time: i64, end_time: i64, chunk: i64) -> Vec<(i64, i64)> {
+    (start_time..=end_time)
+        .step_by(chunk as usize)
+        .map(|start| (start, (start + chunk - 1).min(end_time)))
+        .collect()
+}
+
+/// Queues a task per query of `job` and `chunk` milliseconds of its time
+/// range, which ends now when the job has no `to`. Tasks queued before are
+/// kept. Returns the tasks added.
+pub async fn enqueue(
+    job: &Job,
+    client: &RestClient,
+    pool: &DbPool,
+    chunk: i64,
+    max_attempts: i32,
+) -> Result<usize> {
+    if chunk <= 0 {
+        return Err(Error::ParseStr(chunk.to_string()));
+    }
+    if max_attempts <= 0 {
+        return Err(Error::Config(format!(
+            "max attempts {} is not positive",
+            max_attempts
+        )));
+    }
+    let now = Utc::now();
+    let (start_time, end_time) = job.time_range(now)?;
+    let start_time = start_time.ok_or_else(|| {
+        Error::Config(format!(
+            "job {} needs from or lookback to be queued",
+            job.name
+        ))
+    })? as i64;
+    let end_time = end_time.map_or(now.timestamp_millis(), |end_time| end_time as i64);
+
+    let mut tasks = Vec::new();
+    for query in job.queries(client).await? {
+        for (start, end) in chunks(start_time, end_time, chunk) {
+            tasks.push(NewTask {
+                job: job.name.to_owned(),
+                dataset: job.dataset.as_str(),
+                source: job.market(),
+                symbol: query.symbol.to_owned(),
+                interval: query.interval.to_owned(),
+                row_limit: i32::from(query.limit),
+                start_time: start,
+                end_time: end,
+                validate: job.validate,
+                audit: job.audit,
+                max_attempts,
+                available_at: now.naive_utc(),
+            });
+        }
+    }
+    with_connection(pool, move |connection| {
+        connection.transaction(|connection| {
+            tasks
+                .iter()
+                .map(|task| {
+                    Ok(diesel::insert_into(beholder_tasks::table)
+                        .values(task)
+                        .on_conflict_do_nothing()
+                        .execute(connection)?)
+                })
+                .sum()
+        })
+    })
+    .await
+}
+
+/// Claims the oldest ready task for `lease` milliseconds, including tasks
+/// whose worker let the lease expire. Expired tasks without attempts left
+/// fail instead. Other workers skip the locked row instead of waiting for it.
+fn claim(connection: &mut DbConnection, run_id: &str, lease: i64) -> Result<Option<Task>> {
+    let now = Utc::now().naive_utc();
+    diesel::update(
+        beholder_tasks::table
+            .filter(beholder_tasks::status.eq(RUNNING))
+            .filter(beholder_tasks::available_at.le(now))
+            .filter(beholder_tasks::attempts.ge(beholder_tasks::max_attempts)),
+    )
+    .set((
+        beholder_tasks::status.eq(FAILED),
+        beholder_tasks::error.eq("Lease expired on the last attempt"),
+    ))
+    .execute(connection)?;
+
+    let ready = beholder_tasks::table
+        .filter(beholder_tasks::status.eq_any([PENDING, RUNNING]))
+        .filter(beholder_tasks::available_at.le(now))
+        .filter(beholder_tasks::attempts.lt(beholder_tasks::max_attempts))
+        .order(beholder_tasks::id)
+        .select(Task::as_select());
+    #[cfg(not(feature = "sqlite"))]
+    let task = ready
+        .for_update()
+        .skip_locked()
+        .first(connection)
+        .optional()?;
+    #[cfg(feature = "sqlite")]
+    let task = ready.first(connection).optional()?;
+
+    let Some(mut task) = task else {
+        return Ok(None);
+    };
+    task.attempts += 1;
+    diesel::update(beholder_tasks::table.find(task.id))
+        .set((
+            beholder_tasks::status.eq(RUNNING),
+            beholder_tasks::attempts.eq(task.attempts),
+            beholder_tasks::available_at.eq(now + TimeDelta::milliseconds(lease)),
+            beholder_tasks::run_id.eq(run_id),
+        ))
+        .execute(connection)?;
+    Ok(Some(task))
+}
+
+/// Records the outcome of `task`, a failed task with attempts left is
+/// retried after `retry_delay` milliseconds per attempt. Returns false when
+/// another worker claimed the task since, whose outcome is kept.
+fn complete(
+    connection: &mut DbConnection,
+    task: &Task,
+    run_id: &str,
+    error: Option<String>,
+    retry_delay: i64,
+) -> Result<bool> {
+    let now = Utc::now().naive_utc();
+    let (status, available_at) = match error {
+        None => (SUCCEEDED, now),
+        Some(_) if task.attempts < task.max_attempts => (
+            PENDING,
+            now + TimeDelta::milliseconds(retry_delay * i64::from(task.attempts)),
+        ),
+        Some(_) => (FAILED, now),
+    };
+    let updated = diesel::update(
+        beholder_tasks::table
+            .find(task.id)
+            .filter(beholder_tasks::run_id.eq(run_id))
+            .filter(beholder_tasks::attempts.eq(task.attempts)),
+    )
+    .set((
+        beholder_tasks::status.eq(status),
+        beholder_tasks::available_at.eq(available_at),
+        beholder_tasks::error.eq(error),
+    ))
+    .execute(connection)?;
+    Ok(updated > 0)
+}
+
+/// Whether any task may still run, now or after a retry delay.
+fn unfinished(connection: &mut DbConnection) -> Result<bool> {
+    let count: i64 = beholder_tasks::table
+        .filter(beholder_tasks::status.eq_any([PENDING, RUNNING]))
+        .filter(beholder_tasks::attempts.lt(beholder_tasks::max_attempts))
+        .count()
+        .get_result(connection)?;
+    Ok(count > 0)
+}
+
+/// Timings of the worker in milliseconds.
+#[derive(Debug, PartialEq)]
+struct Timings {
+    lease: i64,
+    retry_delay: i64,
+    poll_interval: i64,
+}
+
+impl Timings {
+    /// Reads the timings of `options`, which must all be positive.
+    fn new(options: &WorkerOptions) -> Result<Self> {
+        let positive = |name: &str, interval: &str| match interval_to_millis(interval)? {
+            millis if millis > 0 => Ok(millis),
+            _ => Err(Error::Config(format!(
+                "{} {} is not positive",
+                name, interval
+            ))),
+        };
+        Ok(Self {
+            lease: positive("lease", &options.lease)?,
+            retry_delay: positive("retry delay", &options.retry_delay)?,
+            poll_interval: positive("poll interval", &options.poll_interval)?,
+        })
+    }
+}
+
+/// Claims and runs one task after another. Returns the tasks that used up
+/// their attempts once no task is left and `exit_when_done`.
+async fn work_one(
+    pool: &DbPool,
+    client: &RestClient,
+    timings: &Timings,
+    exit_when_done: bool,
+    run_id: &str,
+) -> Result<Vec<(String, Error)>> {
+    let mut failed = Vec::new();
+    loop {
+        let claimant = run_id.to_owned();
+        let lease = timings.lease;
+        let task = with_connection(pool, move |connection| {
+            #[cfg(not(feature = "sqlite"))]
+            return connection.transaction(|connection| claim(connection, &claimant, lease));
+            #[cfg(feature = "sqlite")]
+            return connection
+                .immediate_transaction(|connection| claim(connection, &claimant, lease));
+        })
+        .await?;
+
+        let Some(task) = task else {
+            if exit_when_done && !with_connection(pool, unfinished).await? {
+                return Ok(failed);
+            }
+            tokio::time::sleep(Duration::from_millis(timings.poll_interval as u64)).await;
+            continue;
+        };
+
+        info!("Running {}, attempt {}", task, task.attempts);
+        let error = task.execute(client, pool, run_id).await.err();
+        if let Some(error) = &error {
+            warn!("{} failed: {}", task, error);
+        }
+        let message = error.as_ref().map(|error| error.to_string());
+        let retry_delay = timings.retry_delay;
+        let claimant = run_id.to_owned();
+        let (task, recorded) = with_connection(pool, move |connection| {
+            let recorded = complete(connection, &task, &claimant, message, retry_delay)?;
+            Ok((task, recorded))
+        })
+        .await?;
+        if !recorded {
+            warn!(
+                "{} was claimed by another worker, its outcome is dropped",
+                task
+            );
+            continue;
+        }
+        if let Some(error) = error.filter(|_| task.attempts >= task.max_attempts) {
+            failed.push((task.to_string(), error));
+        }
+    }
+}
+
+/// Runs queued tasks with `options.concurrency` claims at a time, storing
+/// rows as written by `run_id`. Returns the tasks that failed for good.
+pub async fn work(
+    pool: &DbPool,
+    client: &RestClient,
+    options: &WorkerOptions,
+    run_id: &str,
+) -> Result<Vec<(String, Error)>> {
+    let timings = &Timings::new(options)?;
+    let workers = (0..options.concurrency.max(1))
+        .map(|_| work_one(pool, client, timings, options.exit_when_done, run_id));
+    let failed = future::try_join_all(workers).await?;
+    Ok(failed.into_iter().flatten().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{chunks, Timings, WorkerOptions};
+    use crate::result::Error;
+
+    fn options(lease: &str, retry_delay: &str, poll_interval: &str) -> WorkerOptions {
+        WorkerOptions {
+            concurrency: 1,
+            lease: lease.into(),
+            retry_delay: retry_delay.into(),
+            poll_interval: poll_interval.into(),
+            exit_when_done: false,
+        }
+    }
+
+    #[test]
+    fn reject_non_positive_timings() {
+        assert_eq!(
+            Timings::new(&options("10m", "1m", "10s")).unwrap(),
+            Timings {
+                lease: 600_000,
+                retry_delay: 60_000,
+                poll_interval: 10_000,
+            }
+        );
+        for options in [
+            options("0m", "1m", "10s"),
+            options("10m", "-1m", "10s"),
+            options("10m", "1m", "-10s"),
+        ] {
+            assert!(matches!(Timings::new(&options), Err(Error::Config(_))));
+        }
+    }
+
+    #[test]
+    fn split_time_range_into_chunks() {
+        assert_eq!(
+            chunks(0, 299_999, 120_000),
+            vec![(0, 119_999), (120_000, 239_999), (240_000, 299_999)]
+        );
+        assert_eq!(chunks(0, 0, 60_000), vec![(0, 0)]);
+        assert!(chunks(1, 0, 60_000).is_empty());
+    }
+}
@@ -0,0 +1,154 @@
+use crate::binance::MarketEndpoint;
+use log::warn;
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// `REQUEST_WEIGHT` limits per IP and minute of `exchangeInfo`.
+const SPOT_WEIGHT_LIMIT: u32 = 6_000;
+const USDM_WEIGHT_LIMIT: u32 = 2_400;
+
+/// Share of a limit used before pausing, leaving room for other clients on
+/// the same IP and requests in flight.
+const BUDGET_PERCENT: u32 = 90;
+
+const MINUTE: u64 = 60_000;
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_millis() as u64)
+}
+
+#[derive(Debug)]
+struct Window {
+    limit: u32,
+    /// Minutes since the epoch, Binance resets weights on minute boundaries.
+    minute: u64,
+    used: u32,
+    blocked_until: Option<Instant>,
+}
+
+impl Window {
+    fn new(limit: u32) -> Self {
+        Self {
+            limit,
+            minute: 0,
+            used: 0,
+            blocked_until: None,
+        }
+    }
+
+    /// Takes `weight` from the current minute, or tells how long to wait.
+    fn take(&mut self, weight: u32, now: Instant, millis: u64) -> Option<Duration> {
+        if let Some(until) = self.blocked_until {
+            if until > now {
+                return Some(until - now);
+            }
+            self.blocked_until = None;
+        }
+        let minute = millis / MINUTE;
+        if minute != self.minute {
+            self.minute = minute;
+            self.used = 0;
+        }
+        if self.used > 0 && self.used + weight > self.limit * BUDGET_PERCENT / 100 {
+            return Some(Duration::from_millis((minute + 1) * MINUTE - millis));
+        }
+        self.used += weight;
+        None
+    }
+}
+
+/// Tracks the request weight used per market and IP so every fetch of a
+/// process pauses before Binance starts rejecting requests.
+#[derive(Debug)]
+pub struct RateLimiter {
+    spot: Mutex<Window>,
+    usdm: Mutex<Window>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self {
+            spot: Mutex::new(Window::new(SPOT_WEIGHT_LIMIT)),
+            usdm: Mutex::new(Window::new(USDM_WEIGHT_LIMIT)),
+        }
+    }
+}
+
+impl RateLimiter {
+    fn window(&self, market: MarketEndpoint) -> &Mutex<Window> {
+        match market {
+            MarketEndpoint::Spot => &self.spot,
+            MarketEndpoint::USDM => &self.usdm,
+        }
+    }
+
+    /// Waits until a request of `weight` fits into the current minute.
+    pub async fn acquire(&self, market: MarketEndpoint, weight: u32) {
+        loop {
+            let wait =
+                self.window(market)
+                    .lock()
+                    .unwrap()
+                    .take(weight, Instant::now(), now_millis());
+            match wait {
+                Some(wait) => tokio::time::sleep(wait).await,
+                None => return,
+            }
+        }
+    }
+
+    /// Adopts the weight Binance reports in `X-MBX-USED-WEIGHT-1M`, which
+    /// also counts other clients sharing the IP.
+    pub fn record(&self, market: MarketEndpoint, used: u32) {
+        let mut window = self.window(market).lock().unwrap();
+        if window.minute == now_millis() / MINUTE {
+            window.used = window.used.max(used);
+        }
+    }
+
+    /// Stops all requests to `market` after a 429 or 418 for `Retry-After`.
+    pub fn block(&self, market: MarketEndpoint, retry_after: Duration) {
+        warn!("Binance {} asks to back off for {:?}", market, retry_after);
+        let until = Instant::now() + retry_after;
+        let mut window = self.window(market).lock().unwrap();
+        window.blocked_until = window.blocked_until.max(Some(until));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Window, MINUTE};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn pause_before_limit() {
+        let now = Instant::now();
+        let millis = 1_000 * MINUTE + 15_000;
+        let mut window = Window::new(100);
+
+        assert_eq!(window.take(50, now, millis), None);
+        assert_eq!(window.take(40, now, millis), None);
+        // 90 of 100 used, the rest of the minute is reserved
+        assert_eq!(
+            window.take(5, now, millis),
+            Some(Duration::from_millis(45_000))
+        );
+        // The next minute starts over
+        assert_eq!(window.take(5, now, millis + 45_000), None);
+        assert_eq!(window.used, 5);
+    }
+
+    #[test]
+    fn honor_retry_after() {
+        let now = Instant::now();
+        let mut window = Window::new(100);
+        window.blocked_until = Some(now + Duration::from_secs(30));
+
+        assert_eq!(window.take(1, now, MINUTE), Some(Duration::from_secs(30)));
+        assert_eq!(window.take(1, now + Duration::from_secs(30), MINUTE), None);
+    }
+}
@@ -0,0 +1,232 @@
+use crate::binance::MarketEndpoint;
+use crate::result::{Error, Result};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+const RESPONSES: &str = "responses.jsonl";
+const FRAMES: &str = "frames.jsonl";
+
+/// A REST response as received, headers are limited to those Beholder reads.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    /// Market and target such as `USDM /fapi/v1/klines?symbol=BTCUSDT`
+    pub request: String,
+    pub status: u16,
+    pub used_weight: Option<u32>,
+    pub retry_after: Option<u64>,
+    pub body: String,
+}
+
+/// A websocket text frame, connections to the same stream are numbered from
+/// zero.
+#[derive(Debug, Serialize, Deserialize)]
+struct RecordedFrame {
+    stream: String,
+    connection: usize,
+    text: String,
+}
+
+pub fn request_key(market: MarketEndpoint, target: &str) -> String {
+    format!("{} {}", market, target)
+}
+
+/// Exchange traffic saved by `--record-dir` into JSON Lines files, or served
+/// back by `--replay-dir` in the order it was recorded.
+#[derive(Debug)]
+pub enum Recording {
+    Record {
+        responses: Mutex<File>,
+        frames: Mutex<File>,
+        connections: Mutex<HashMap<String, usize>>,
+    },
+    Replay {
+        responses: Mutex<HashMap<String, VecDeque<RecordedResponse>>>,
+        frames: Mutex<HashMap<String, VecDeque<Vec<String>>>>,
+    },
+}
+
+impl Recording {
+    /// Appends to the recordings of `dir`, creating it if needed.
+    pub fn record<P>(dir: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        info!("Recording exchange traffic into {}", dir.display());
+        let open = |name| {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(dir.join(name))
+        };
+        Ok(Self::Record {
+            responses: Mutex::new(open(RESPONSES)?),
+            frames: Mutex::new(open(FRAMES)?),
+            connections: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Loads the recordings of `dir`, either file may be missing.
+    pub fn replay<P>(dir: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let dir = dir.as_ref();
+        info!("Replaying exchange traffic from {}", dir.display());
+
+        let mut responses: HashMap<String, VecDeque<RecordedResponse>> = HashMap::new();
+        for response in read_lines::<RecordedResponse>(&dir.join(RESPONSES))? {
+            responses
+                .entry(response.request.to_owned())
+                .or_default()
+                .push_back(response);
+        }
+        let mut frames: HashMap<String, VecDeque<Vec<String>>> = HashMap::new();
+        for frame in read_lines::<RecordedFrame>(&dir.join(FRAMES))? {
+            let connections = frames.entry(frame.stream).or_default();
+            while connections.len() <= frame.connection {
+                connections.push_back(Vec::new());
+            }
+            connections[frame.connection].push(frame.text);
+        }
+        Ok(Self::Replay {
+            responses: Mutex::new(responses),
+            frames: Mutex::new(frames),
+        })
+    }
+
+    pub fn is_replay(&self) -> bool {
+        matches!(self, Self::Replay { .. })
+    }
+
+    /// Saves a response received from the exchange.
+    pub fn save_response(&self, response: &RecordedResponse) -> Result {
+        if let Self::Record { responses, .. } = self {
+            append(responses, response)?;
+        }
+        Ok(())
+    }
+
+    /// The next recorded response to `request`.
+    pub fn next_response(&self, request: &str) -> Result<RecordedResponse> {
+        let Self::Replay { responses, .. } = self else {
+            return Err(Error::Replay(format!("not replaying {}", request)));
+        };
+        responses
+            .lock()
+            .unwrap()
+            .get_mut(request)
+            .and_then(VecDeque::pop_front)
+            .ok_or_else(|| Error::Replay(format!("no recorded response to {}", request)))
+    }
+
+    /// Numbers a new connection to `stream` for its frames.
+    pub fn open_connection(&self, stream: &str) -> usize {
+        match self {
+            Self::Record { connections, .. } => {
+                let mut connections = connections.lock().unwrap();
+                let count = connections.entry(stream.to_owned()).or_default();
+                *count += 1;
+                *count - 1
+            }
+            Self::Replay { .. } => 0,
+        }
+    }
+
+    /// Saves a frame received on `connection` of `stream`.
+    pub fn save_frame(&self, stream: &str, connection: usize, text: &str) -> Result {
+        if let Self::Record { frames, .. } = self {
+            let frame = RecordedFrame {
+                stream: stream.to_owned(),
+                connection,
+                text: text.to_owned(),
+            };
+            append(frames, &frame)?;
+        }
+        Ok(())
+    }
+
+    /// Frames of the next recorded connection to `stream`, `None` once every
+    /// connection was replayed.
+    pub fn next_connection(&self, stream: &str) -> Option<Vec<String>> {
+        match self {
+            Self::Replay { frames, .. } => frames.lock().unwrap().get_mut(stream)?.pop_front(),
+            Self::Record { .. } => None,
+        }
+    }
+}
+
+fn append<T>(file: &Mutex<File>, value: &T) -> Result
+where
+    T: Serialize,
+{
+    let mut line = serde_json::to_string(value)?;
+    line.push('\n');
+    file.lock().unwrap().write_all(line.as_bytes())?;
+    Ok(())
+}
+
+fn read_lines<T>(path: &Path) -> Result<Vec<T>>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    BufReader::new(File::open(path)?)
+        .lines()
+        .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RecordedResponse, Recording};
+
+    #[test]
+    fn replay_what_was_recorded() {
+        let dir = std::env::temp_dir().join(format!("beholder-recording-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let recording = Recording::record(&dir).unwrap();
+        for status in [503, 200] {
+            let response = RecordedResponse {
+                request: "USDM /fapi/v1/klines?symbol=BTCUSDT".into(),
+                status,
+                used_weight: Some(5),
+                retry_after: None,
+                body: "[]".into(),
+            };
+            recording.save_response(&response).unwrap();
+        }
+        for text in ["a", "b"] {
+            let connection = recording.open_connection("/stream?streams=btcusdt@kline_1m");
+            recording
+                .save_frame("/stream?streams=btcusdt@kline_1m", connection, text)
+                .unwrap();
+        }
+        drop(recording);
+
+        let replay = Recording::replay(&dir).unwrap();
+        let request = "USDM /fapi/v1/klines?symbol=BTCUSDT";
+        assert_eq!(replay.next_response(request).unwrap().status, 503);
+        assert_eq!(replay.next_response(request).unwrap().status, 200);
+        assert!(replay.next_response(request).is_err());
+
+        let stream = "/stream?streams=btcusdt@kline_1m";
+        assert_eq!(replay.next_connection(stream), Some(vec!["a".to_owned()]));
+        assert_eq!(replay.next_connection(stream), Some(vec!["b".to_owned()]));
+        assert_eq!(replay.next_connection(stream), None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
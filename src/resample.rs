@@ -0,0 +1,522 @@
+use crate::binance::{interval_to_millis, Kline, MarketEndpoint};
+use crate::database::DbConnection;
+use crate::export::{each_kline_batch, Filter};
+use crate::result::{Error, Result};
+use crate::schema::{binance_klines, binance_local_klines};
+use chrono::{DateTime, Datelike, Days, LocalResult, NaiveDate, NaiveTime, TimeDelta, TimeZone};
+use chrono_tz::Tz;
+use diesel::prelude::*;
+use rust_decimal::Decimal;
+use std::{collections::HashSet, str::FromStr};
+
+/// Rows are written in chunks to stay well below the bind parameter limit.
+const CHUNK_SIZE: usize = 1_000;
+
+const WEEK: i64 = 604_800_000;
+
+/// 1970-01-01 was a Thursday, Binance weeks start on Monday.
+const MONDAY_OFFSET: i64 = 345_600_000;
+
+#[derive(Debug, PartialEq)]
+pub struct IncompleteBucket {
+    pub source: MarketEndpoint,
+    pub symbol: String,
+    pub open_time: i64,
+    pub count: usize,
+    pub expected: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct ResampleReport {
+    pub written: usize,
+    pub incomplete: Vec<IncompleteBucket>,
+}
+
+/// Start of the bucket of length `interval` holding `time`. Weekly buckets
+/// start on Monday like Binance's own `1w` klines.
+pub fn bucket_open_time(time: i64, interval: i64) -> i64 {
+    let offset = if interval % WEEK == 0 {
+        MONDAY_OFFSET
+    } else {
+        0
+    };
+    (time - offset).div_euclid(interval) * interval + offset
+}
+
+pub fn decimal(value: &str) -> Result<Decimal> {
+    Decimal::from_str(value).map_err(|_| Error::ParseStr(value.to_owned()))
+}
+
+fn sum(klines: &[Kline], value: impl Fn(&Kline) -> &str) -> Result<String> {
+    klines
+        .iter()
+        .map(|kline| decimal(value(kline)))
+        .sum::<Result<Decimal>>()
+        .map(|sum| sum.to_string())
+}
+
+/// Aggregates time ordered klines into one derived kline spanning
+/// `open_time` to `close_time`.
+pub fn aggregate(klines: &[Kline], open_time: i64, close_time: i64) -> Result<Kline> {
+    let (first, last) = match (klines.first(), klines.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return Err(Error::ParseStr("empty bucket".into())),
+    };
+
+    let mut high = decimal(&first.high)?;
+    let mut low = decimal(&first.low)?;
+    for kline in klines {
+        high = high.max(decimal(&kline.high)?);
+        low = low.min(decimal(&kline.low)?);
+    }
+
+    Ok(Kline {
+        source: first.source,
+        symbol: first.symbol.to_owned(),
+        open_time,
+        close_time,
+        open: first.open.to_owned(),
+        high: high.to_string(),
+        low: low.to_string(),
+        close: last.close.to_owned(),
+        base_volume: sum(klines, |kline| &kline.base_volume)?,
+        quote_volume: sum(klines, |kline| &kline.quote_volume)?,
+        buy_base_volume: sum(klines, |kline| &kline.buy_base_volume)?,
+        buy_quote_volume: sum(klines, |kline| &kline.buy_quote_volume)?,
+        number_of_trades: klines.iter().map(|kline| kline.number_of_trades).sum(),
+        derived: true,
+    })
+}
+
+/// Upserts derived klines unless Binance already provided the same bar.
+fn write_derived(klines: &[Kline], connection: &mut DbConnection) -> Result<usize> {
+    let mut written = 0;
+    for series in klines.chunk_by(|a, b| a.source == b.source && a.symbol == b.symbol) {
+        for chunk in series.chunks(CHUNK_SIZE) {
+            let open_times: Vec<i64> = chunk.iter().map(|kline| kline.open_time).collect();
+            let native: HashSet<(i64, i64)> = binance_klines::table
+                .select((binance_klines::open_time, binance_klines::close_time))
+                .filter(binance_klines::source.eq(chunk[0].source))
+                .filter(binance_klines::symbol.eq(&chunk[0].symbol))
+                .filter(binance_klines::open_time.eq_any(open_times))
+                .filter(binance_klines::derived.eq(false))
+                .load::<(i64, i64)>(connection)?
+                .into_iter()
+                .collect();
+
+            let derived: Vec<Kline> = chunk
+                .iter()
+                .filter(|kline| !native.contains(&(kline.open_time, kline.close_time)))
+                .cloned()
+                .collect();
+            written += Kline::upsert_all(&derived, None, connection)?;
+        }
+    }
+    Ok(written)
+}
+
+/// Feeds the native klines selected by `filter` to `finish`, grouped into
+/// runs of the same series sharing a bucket `key`.
+fn each_bucket<K: PartialEq>(
+    connection: &mut DbConnection,
+    filter: &Filter,
+    key: impl Fn(&Kline) -> Result<K>,
+    mut finish: impl FnMut(K, Vec<Kline>) -> Result,
+) -> Result {
+    let mut bucket: Option<(K, Vec<Kline>)> = None;
+    each_kline_batch(connection, filter, |klines| {
+        for kline in klines.iter().filter(|kline| !kline.derived) {
+            let kline_key = key(kline)?;
+            let same_bucket = bucket.as_ref().is_some_and(|(key, klines)| {
+                *key == kline_key
+                    && klines[0].source == kline.source
+                    && klines[0].symbol == kline.symbol
+            });
+            if !same_bucket {
+                if let Some((key, klines)) = bucket.take() {
+                    finish(key, klines)?;
+                }
+                bucket = Some((kline_key, Vec::new()));
+            }
+            if let Some((_, klines)) = &mut bucket {
+                klines.push(kline.clone());
+            }
+        }
+        Ok(())
+    })?;
+    if let Some((key, klines)) = bucket {
+        finish(key, klines)?;
+    }
+    Ok(())
+}
+
+/// Records buckets holding fewer than `expected` klines and tells whether the
+/// bucket should be written.
+fn check_complete(
+    klines: &[Kline],
+    open_time: i64,
+    expected: usize,
+    include_incomplete: bool,
+    report: &mut ResampleReport,
+) -> bool {
+    if klines.len() >= expected {
+        return true;
+    }
+    report.incomplete.push(IncompleteBucket {
+        source: klines[0].source,
+        symbol: klines[0].symbol.to_owned(),
+        open_time,
+        count: klines.len(),
+        expected,
+    });
+    include_incomplete
+}
+
+/// `filter` narrowed to klines of its interval, `1m` unless given, and that
+/// interval in milliseconds. Klines of other intervals would otherwise be
+/// mixed into the same buckets.
+fn source_filter(filter: &Filter) -> Result<(Filter, i64)> {
+    let interval = filter.interval.clone().unwrap_or_else(|| "1m".to_owned());
+    let millis = interval_to_millis(&interval)?;
+    let filter = Filter {
+        interval: Some(interval),
+        ..filter.clone()
+    };
+    Ok((filter, millis))
+}
+
+/// Aggregates the stored klines selected by `filter`, whose interval defaults
+/// to `1m`, into `interval` klines marked as derived.
+pub fn resample(
+    connection: &mut DbConnection,
+    filter: &Filter,
+    interval: &str,
+    include_incomplete: bool,
+) -> Result<ResampleReport> {
+    let (filter, source_interval) = source_filter(filter)?;
+    let interval = interval_to_millis(interval)?;
+    if interval <= source_interval || interval % source_interval != 0 {
+        return Err(Error::ParseStr(format!(
+            "{}ms is not a multiple of {}ms",
+            interval, source_interval
+        )));
+    }
+    let expected = (interval / source_interval) as usize;
+
+    let mut report = ResampleReport::default();
+    let mut pending = Vec::new();
+    each_bucket(
+        connection,
+        &filter,
+        |kline| Ok(bucket_open_time(kline.open_time, interval)),
+        |open_time, klines| {
+            if check_complete(
+                &klines,
+                open_time,
+                expected,
+                include_incomplete,
+                &mut report,
+            ) {
+                pending.push(aggregate(&klines, open_time, open_time + interval - 1)?);
+            }
+            Ok(())
+        },
+    )?;
+
+    report.written = connection.transaction(|connection| write_derived(&pending, connection))?;
+    Ok(report)
+}
+
+/// Daily or Monday anchored weekly sessions of a local timezone, opening
+/// `start` after local midnight.
+#[derive(Debug, Clone, Copy)]
+pub struct Session {
+    pub timezone: Tz,
+    pub start: NaiveTime,
+    pub weekly: bool,
+}
+
+impl Session {
+    pub fn new(timezone: &str, start: &str, interval: &str) -> Result<Self> {
+        let timezone = Tz::from_str(timezone).map_err(|_| Error::ParseStr(timezone.to_owned()))?;
+        let start = NaiveTime::parse_from_str(start, "%H:%M")
+            .map_err(|_| Error::ParseStr(start.to_owned()))?;
+        let weekly = match interval {
+            "1d" => false,
+            "1w" => true,
+            _ => return Err(Error::ParseStr(interval.to_owned())),
+        };
+        Ok(Self {
+            timezone,
+            start,
+            weekly,
+        })
+    }
+
+    pub fn interval(&self) -> &'static str {
+        if self.weekly {
+            "1w"
+        } else {
+            "1d"
+        }
+    }
+
+    fn start_millis(&self) -> i64 {
+        (self.start - NaiveTime::MIN).num_milliseconds()
+    }
+
+    /// Local date of the session holding `time`, the Monday for weeks.
+    pub fn local_date(&self, time: i64) -> Result<NaiveDate> {
+        let utc = DateTime::from_timestamp_millis(time)
+            .ok_or_else(|| Error::ParseStr(time.to_string()))?;
+        let local = utc.with_timezone(&self.timezone).naive_local();
+        let date = (local - (self.start - NaiveTime::MIN)).date();
+        if self.weekly {
+            Ok(date - Days::new(date.weekday().num_days_from_monday().into()))
+        } else {
+            Ok(date)
+        }
+    }
+
+    /// Instant the session of `date` opens. A start skipped by a daylight
+    /// saving transition moves to the end of the gap, a repeated start opens
+    /// at its first occurrence.
+    pub fn open_time(&self, date: NaiveDate) -> i64 {
+        let mut local = date.and_time(self.start);
+        loop {
+            match self.timezone.from_local_datetime(&local) {
+                LocalResult::Single(time) | LocalResult::Ambiguous(time, _) => {
+                    return time.timestamp_millis()
+                }
+                LocalResult::None => local += TimeDelta::minutes(1),
+            }
+        }
+    }
+
+    /// Open and close time of the session of `date`.
+    pub fn bounds(&self, date: NaiveDate) -> (i64, i64) {
+        let next = date + Days::new(if self.weekly { 7 } else { 1 });
+        (self.open_time(date), self.open_time(next) - 1)
+    }
+}
+
+/// A session kline keyed on its local date.
+#[derive(Debug, PartialEq, Insertable, AsChangeset)]
+#[diesel(table_name = binance_local_klines)]
+pub struct LocalKline {
+    pub source: MarketEndpoint,
+    pub symbol: String,
+    pub timezone: String,
+    pub session_start: i64,
+    pub interval: String,
+    pub local_date: NaiveDate,
+    pub open_time: i64,
+    pub close_time: i64,
+    pub open: String,
+    pub high: String,
+    pub low: String,
+    pub close: String,
+    pub base_volume: String,
+    pub quote_volume: String,
+    pub buy_base_volume: String,
+    pub buy_quote_volume: String,
+    pub number_of_trades: i64,
+}
+
+impl LocalKline {
+    fn new(session: &Session, local_date: NaiveDate, kline: Kline) -> Self {
+        Self {
+            source: kline.source,
+            symbol: kline.symbol,
+            timezone: session.timezone.name().to_owned(),
+            session_start: session.start_millis(),
+            interval: session.interval().to_owned(),
+            local_date,
+            open_time: kline.open_time,
+            close_time: kline.close_time,
+            open: kline.open,
+            high: kline.high,
+            low: kline.low,
+            close: kline.close,
+            base_volume: kline.base_volume,
+            quote_volume: kline.quote_volume,
+            buy_base_volume: kline.buy_base_volume,
+            buy_quote_volume: kline.buy_quote_volume,
+            number_of_trades: kline.number_of_trades,
+        }
+    }
+
+    pub fn upsert(&self, connection: &mut DbConnection) -> QueryResult<usize> {
+        diesel::insert_into(binance_local_klines::table)
+            .values(self)
+            .on_conflict((
+                binance_local_klines::symbol,
+                binance_local_klines::source,
+                binance_local_klines::timezone,
+                binance_local_klines::session_start,
+                binance_local_klines::interval,
+                binance_local_klines::local_date,
+            ))
+            .do_update()
+            .set(self)
+            .execute(connection)
+    }
+}
+
+/// Aggregates the stored klines selected by `filter`, whose interval defaults
+/// to `1m`, into the sessions of `session` stored in `binance_local_klines`.
+pub fn resample_local(
+    connection: &mut DbConnection,
+    filter: &Filter,
+    session: &Session,
+    include_incomplete: bool,
+) -> Result<ResampleReport> {
+    let (filter, source_interval) = source_filter(filter)?;
+
+    let mut report = ResampleReport::default();
+    let mut pending = Vec::new();
+    each_bucket(
+        connection,
+        &filter,
+        |kline| session.local_date(kline.open_time),
+        |local_date, klines| {
+            let (open_time, close_time) = session.bounds(local_date);
+            let length = close_time + 1 - open_time;
+            if length % source_interval != 0 || open_time % source_interval != 0 {
+                return Err(Error::ParseStr(format!(
+                    "session of {} does not align with {}ms klines",
+                    local_date, source_interval
+                )));
+            }
+            let expected = (length / source_interval) as usize;
+            if check_complete(
+                &klines,
+                open_time,
+                expected,
+                include_incomplete,
+                &mut report,
+            ) {
+                let kline = aggregate(&klines, open_time, close_time)?;
+                pending.push(LocalKline::new(session, local_date, kline));
+            }
+            Ok(())
+        },
+    )?;
+
+    report.written = connection.transaction(|connection| {
+        pending
+            .iter()
+            .map(|kline| kline.upsert(connection))
+            .sum::<QueryResult<usize>>()
+    })?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{aggregate, bucket_open_time, source_filter, Session};
+    use crate::binance::{Kline, MarketEndpoint};
+    use crate::export::Filter;
+    use chrono::NaiveDate;
+
+    fn kline(open_time: i64, open: &str, high: &str, low: &str, close: &str) -> Kline {
+        Kline {
+            source: MarketEndpoint::USDM,
+            symbol: "BTCUSDT".into(),
+            open_time,
+            close_time: open_time + 59_999,
+            open: open.into(),
+            high: high.into(),
+            low: low.into(),
+            close: close.into(),
+            base_volume: "1.5".into(),
+            quote_volume: "150.25".into(),
+            buy_base_volume: "0.5".into(),
+            buy_quote_volume: "50.05".into(),
+            number_of_trades: 10,
+            derived: false,
+        }
+    }
+
+    #[test]
+    fn align_buckets() {
+        // 2024-01-03T13:37:00Z, a Wednesday
+        let time = 1_704_289_020_000;
+        assert_eq!(bucket_open_time(time, 3_600_000), 1_704_286_800_000);
+        assert_eq!(bucket_open_time(time, 86_400_000), 1_704_240_000_000);
+        // Monday 2024-01-01T00:00:00Z
+        assert_eq!(bucket_open_time(time, 604_800_000), 1_704_067_200_000);
+    }
+
+    #[test]
+    fn select_source_interval() {
+        let filter = Filter {
+            market: Some(MarketEndpoint::USDM),
+            symbol: vec!["BTCUSDT".into()],
+            interval: None,
+            start_time: None,
+            end_time: None,
+        };
+        let (source, millis) = source_filter(&filter).unwrap();
+        assert_eq!(source.interval.as_deref(), Some("1m"));
+        assert_eq!(source.symbol, filter.symbol);
+        assert_eq!(millis, 60_000);
+
+        let filter = Filter {
+            interval: Some("5m".into()),
+            ..filter
+        };
+        assert_eq!(source_filter(&filter).unwrap().1, 300_000);
+    }
+
+    #[test]
+    fn aggregate_klines() {
+        let klines = vec![
+            kline(0, "100", "105.5", "99", "101"),
+            kline(60_000, "101", "103", "98.25", "102"),
+            kline(120_000, "102", "104", "100", "103.75"),
+        ];
+
+        let derived = aggregate(&klines, 0, 179_999).unwrap();
+
+        assert_eq!(derived.open, "100");
+        assert_eq!(derived.high, "105.5");
+        assert_eq!(derived.low, "98.25");
+        assert_eq!(derived.close, "103.75");
+        assert_eq!(derived.base_volume, "4.5");
+        assert_eq!(derived.quote_volume, "450.75");
+        assert_eq!(derived.buy_quote_volume, "150.15");
+        assert_eq!(derived.number_of_trades, 30);
+        assert_eq!(derived.close_time, 179_999);
+        assert!(derived.derived);
+    }
+
+    #[test]
+    fn local_sessions() {
+        // 2024-01-03T13:37:00Z is 21:37 in Shanghai
+        let time = 1_704_289_020_000;
+        let session = Session::new("Asia/Shanghai", "00:00", "1d").unwrap();
+        let date = session.local_date(time).unwrap();
+        assert_eq!(date, NaiveDate::from_ymd_opt(2024, 1, 3).unwrap());
+        // 2024-01-02T16:00:00Z to 2024-01-03T15:59:59.999Z
+        assert_eq!(session.bounds(date), (1_704_211_200_000, 1_704_297_599_999));
+
+        let session = Session::new("Asia/Shanghai", "22:00", "1w").unwrap();
+        let date = session.local_date(time).unwrap();
+        assert_eq!(date, NaiveDate::from_ymd_opt(2024, 1, 1).unwrap());
+        // Monday 2024-01-01T22:00+08:00
+        assert_eq!(session.open_time(date), 1_704_117_600_000);
+    }
+
+    #[test]
+    fn daylight_saving_sessions() {
+        let session = Session::new("Europe/London", "00:00", "1d").unwrap();
+        let (open_time, close_time) = session.bounds(NaiveDate::from_ymd_opt(2024, 3, 31).unwrap());
+        assert_eq!(close_time + 1 - open_time, 23 * 3_600_000);
+
+        // 01:30 does not exist on 2024-03-31 and opens at 02:00 BST instead
+        let session = Session::new("Europe/London", "01:30", "1d").unwrap();
+        let open_time = session.open_time(NaiveDate::from_ymd_opt(2024, 3, 31).unwrap());
+        assert_eq!(open_time, 1_711_846_800_000);
+    }
+}
@@ -0,0 +1,300 @@
+use crate::binance::MarketEndpoint;
+use crate::rate_limit::RateLimiter;
+use crate::recording::{request_key, RecordedResponse, Recording};
+use crate::result::{Error, Result};
+use crate::retry::{Backoff, DEFAULT_RETRIES};
+use log::debug;
+use reqwest::{header::HeaderMap, Client, Request};
+use serde::{de::DeserializeOwned, Deserialize};
+use std::{str::FromStr, time::Duration};
+
+const SPOT_API: &str = "https://api.binance.com";
+const USDM_API: &str = "https://fapi.binance.com";
+const SPOT_WS: &str = "wss://stream.binance.com:9443";
+const USDM_WS: &str = "wss://fstream.binance.com";
+
+/// Used when a 429 or 418 carries no `Retry-After`.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// Seconds a request may take from connecting to reading its body, after
+/// which it fails as a transient timeout and is retried.
+const DEFAULT_TIMEOUT: u64 = 30;
+const DEFAULT_CONNECT_TIMEOUT: u64 = 10;
+
+/// The body of a rejected request.
+#[derive(Debug, Deserialize)]
+struct ApiError {
+    code: i64,
+    msg: String,
+}
+
+/// Base URLs of the REST and websocket APIs of one market, such as those of
+/// the testnet. Unset URLs fall back to the production APIs.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BaseUrls {
+    pub api_base_url: Option<String>,
+    pub ws_base_url: Option<String>,
+}
+
+impl BaseUrls {
+    /// These URLs, completed by those of `fallback`.
+    pub fn or(&self, fallback: &Self) -> Self {
+        Self {
+            api_base_url: self
+                .api_base_url
+                .clone()
+                .or_else(|| fallback.api_base_url.clone()),
+            ws_base_url: self
+                .ws_base_url
+                .clone()
+                .or_else(|| fallback.ws_base_url.clone()),
+        }
+    }
+}
+
+/// Options of the clients talking to Binance.
+#[derive(Debug, clap::Args)]
+pub struct ClientOptions {
+    /// Retries of requests failing with timeouts, server errors or dropped
+    /// connections
+    #[clap(long, value_parser, default_value_t = DEFAULT_RETRIES)]
+    pub retries: u32,
+
+    /// Send REST requests of every market to this URL, such as a testnet or
+    /// a local stub
+    #[clap(long, value_parser)]
+    pub api_base_url: Option<String>,
+
+    /// Open websocket streams of every market at this URL
+    #[clap(long, value_parser)]
+    pub ws_base_url: Option<String>,
+
+    /// Seconds before a stalled REST request fails and is retried
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..), default_value_t = DEFAULT_TIMEOUT)]
+    pub timeout: u64,
+
+    /// Seconds before an unanswered connection attempt fails and is retried
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..), default_value_t = DEFAULT_CONNECT_TIMEOUT)]
+    pub connect_timeout: u64,
+}
+
+impl ClientOptions {
+    /// A client preferring the URLs of these options over `spot` and `usdm`,
+    /// which usually come from a config.
+    pub fn client(
+        &self,
+        spot: &BaseUrls,
+        usdm: &BaseUrls,
+        recording: Option<Recording>,
+    ) -> RestClient {
+        let urls = BaseUrls {
+            api_base_url: self.api_base_url.clone(),
+            ws_base_url: self.ws_base_url.clone(),
+        };
+        RestClient {
+            http: http_client(self.timeout, self.connect_timeout),
+            backoff: Backoff::new(self.retries),
+            spot: urls.or(spot),
+            usdm: urls.or(usdm),
+            recording,
+            ..RestClient::default()
+        }
+    }
+}
+
+/// An HTTP client giving up on requests after `timeout` seconds and on
+/// connection attempts after `connect_timeout` seconds.
+fn http_client(timeout: u64, connect_timeout: u64) -> Client {
+    Client::builder()
+        .timeout(Duration::from_secs(timeout))
+        .connect_timeout(Duration::from_secs(connect_timeout))
+        .build()
+        .expect("Cannot build the HTTP client")
+}
+
+/// Client for the public REST endpoints of Binance, which also knows where
+/// the streams of each market are served. Every request passes the shared
+/// `RateLimiter`, which learns the used weight from the response headers, and
+/// transient failures are retried.
+#[derive(Debug)]
+pub struct RestClient {
+    http: Client,
+    limiter: RateLimiter,
+    backoff: Backoff,
+    spot: BaseUrls,
+    usdm: BaseUrls,
+    recording: Option<Recording>,
+}
+
+impl Default for RestClient {
+    fn default() -> Self {
+        Self {
+            http: http_client(DEFAULT_TIMEOUT, DEFAULT_CONNECT_TIMEOUT),
+            limiter: RateLimiter::default(),
+            backoff: Backoff::default(),
+            spot: BaseUrls::default(),
+            usdm: BaseUrls::default(),
+            recording: None,
+        }
+    }
+}
+
+impl RestClient {
+    /// Traffic saved or replayed instead of reaching the exchange.
+    pub fn recording(&self) -> Option<&Recording> {
+        self.recording.as_ref()
+    }
+
+    fn base_urls(&self, market: MarketEndpoint) -> &BaseUrls {
+        match market {
+            MarketEndpoint::Spot => &self.spot,
+            MarketEndpoint::USDM => &self.usdm,
+        }
+    }
+
+    fn api_base_url(&self, market: MarketEndpoint) -> &str {
+        let default = match market {
+            MarketEndpoint::Spot => SPOT_API,
+            MarketEndpoint::USDM => USDM_API,
+        };
+        let url = self.base_urls(market).api_base_url.as_deref();
+        url.unwrap_or(default).trim_end_matches('/')
+    }
+
+    /// Where websocket streams of `market` are opened.
+    pub fn ws_base_url(&self, market: MarketEndpoint) -> &str {
+        let default = match market {
+            MarketEndpoint::Spot => SPOT_WS,
+            MarketEndpoint::USDM => USDM_WS,
+        };
+        let url = self.base_urls(market).ws_base_url.as_deref();
+        url.unwrap_or(default).trim_end_matches('/')
+    }
+
+    /// Sends a GET request of `weight` to `path` of the market's API.
+    pub async fn get<T>(
+        &self,
+        market: MarketEndpoint,
+        path: &str,
+        parameters: &[(&str, String)],
+        weight: u32,
+    ) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        self.backoff
+            .retry(|| self.send(market, path, parameters, weight))
+            .await
+    }
+
+    async fn send<T>(
+        &self,
+        market: MarketEndpoint,
+        path: &str,
+        parameters: &[(&str, String)],
+        weight: u32,
+    ) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let url = format!("{}{}", self.api_base_url(market), path);
+        let request = self.http.get(url).query(parameters).build()?;
+        let target = match request.url().query() {
+            Some(query) => format!("{}?{}", request.url().path(), query),
+            None => request.url().path().to_owned(),
+        };
+        let key = request_key(market, &target);
+
+        let response = match &self.recording {
+            Some(recording) if recording.is_replay() => recording.next_response(&key)?,
+            recording => {
+                self.limiter.acquire(market, weight).await;
+                debug!("GET {}", request.url());
+                let response = self.execute(key, request).await?;
+                if let Some(recording) = recording {
+                    recording.save_response(&response)?;
+                }
+                response
+            }
+        };
+        if let Some(used_weight) = response.used_weight {
+            self.limiter.record(market, used_weight);
+        }
+
+        match response.status {
+            429 | 418 => {
+                let retry_after = response
+                    .retry_after
+                    .map_or(DEFAULT_RETRY_AFTER, Duration::from_secs);
+                self.limiter.block(market, retry_after);
+                Err(Error::RateLimited(retry_after))
+            }
+            200..=299 => serde_json::from_str(&response.body).map_err(|error| {
+                Error::Api(response.status, format!("Unexpected response: {}", error))
+            }),
+            status => {
+                let message = match serde_json::from_str::<ApiError>(&response.body) {
+                    Ok(error) => format!("{} {}", error.code, error.msg),
+                    Err(_) => response.body,
+                };
+                Err(Error::Api(status, message))
+            }
+        }
+    }
+
+    async fn execute(&self, key: String, request: Request) -> Result<RecordedResponse> {
+        let response = self.http.execute(request).await?;
+        let headers = response.headers();
+        Ok(RecordedResponse {
+            request: key,
+            status: response.status().as_u16(),
+            used_weight: header(headers, "x-mbx-used-weight-1m")
+                .or_else(|| header(headers, "x-mbx-used-weight")),
+            retry_after: header(headers, "retry-after"),
+            body: response.text().await?,
+        })
+    }
+}
+
+fn header<T>(headers: &HeaderMap, name: &str) -> Option<T>
+where
+    T: FromStr,
+{
+    headers.get(name)?.to_str().ok()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BaseUrls, ClientOptions};
+    use crate::binance::MarketEndpoint;
+
+    #[test]
+    fn prefer_flags_over_config() {
+        let options = ClientOptions {
+            retries: 0,
+            api_base_url: Some("http://localhost:8080/".into()),
+            ws_base_url: None,
+            timeout: 30,
+            connect_timeout: 10,
+        };
+        let spot = BaseUrls {
+            api_base_url: Some("https://testnet.binance.vision".into()),
+            ws_base_url: Some("wss://testnet.binance.vision".into()),
+        };
+        let client = options.client(&spot, &BaseUrls::default(), None);
+
+        assert_eq!(
+            client.api_base_url(MarketEndpoint::Spot),
+            "http://localhost:8080"
+        );
+        assert_eq!(
+            client.ws_base_url(MarketEndpoint::Spot),
+            "wss://testnet.binance.vision"
+        );
+        assert_eq!(
+            client.ws_base_url(MarketEndpoint::USDM),
+            "wss://fstream.binance.com"
+        );
+    }
+}
@@ -1,24 +1,56 @@
-use std::{error, fmt, io, num, result};
+use std::{error, fmt, io, num, path::PathBuf, result, time::Duration};
 
 #[derive(Debug)]
 pub enum Error {
     IO(io::Error),
-    CSV(csv::Error),
-    BinanceClient(binance_client::errors::Error),
+    Api(u16, String),
+    Arrow(arrow::error::ArrowError),
+    Parquet(parquet::errors::ParquetError),
+    Csv(csv::Error),
+    Checksum(PathBuf),
+    Config(String),
+    Connection(diesel::ConnectionError),
     Diesel(diesel::result::Error),
+    Json(serde_json::Error),
+    Locked(String),
+    Migration(Box<dyn error::Error + Send + Sync>),
     ParseStr(String),
+    Pool(diesel::r2d2::PoolError),
+    RateLimited(Duration),
+    Replay(String),
+    Request(reqwest::Error),
+    Toml(toml::de::Error),
     TryFromNumber(num::TryFromIntError),
+    WebSocket(Box<tokio_tungstenite::tungstenite::Error>),
+    Zip(zip::result::ZipError),
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Self::IO(error) => fmt::Display::fmt(error, f),
-            Self::CSV(error) => fmt::Display::fmt(error, f),
-            Self::BinanceClient(error) => fmt::Display::fmt(error, f),
+            Self::Api(status, message) => write!(f, "Binance responded {}: {}", status, message),
+            Self::Arrow(error) => fmt::Display::fmt(error, f),
+            Self::Parquet(error) => fmt::Display::fmt(error, f),
+            Self::Csv(error) => fmt::Display::fmt(error, f),
+            Self::Checksum(path) => write!(f, "Checksum mismatch of {}", path.display()),
+            Self::Config(message) => write!(f, "Invalid config: {}", message),
+            Self::Connection(error) => fmt::Display::fmt(error, f),
             Self::Diesel(error) => fmt::Display::fmt(error, f),
+            Self::Json(error) => fmt::Display::fmt(error, f),
+            Self::Locked(job) => write!(f, "Another run of {} holds its lock", job),
+            Self::Migration(error) => fmt::Display::fmt(error, f),
+            Self::ParseStr(string) => write!(f, "Cannot parse {:?}", string),
+            Self::Pool(error) => fmt::Display::fmt(error, f),
+            Self::RateLimited(retry_after) => {
+                write!(f, "Rate limited by Binance for {:?}", retry_after)
+            }
+            Self::Replay(message) => write!(f, "Cannot replay: {}", message),
+            Self::Request(error) => fmt::Display::fmt(error, f),
+            Self::Toml(error) => fmt::Display::fmt(error, f),
             Self::TryFromNumber(error) => fmt::Display::fmt(error, f),
-            error => fmt::Display::fmt(error, f),
+            Self::WebSocket(error) => fmt::Display::fmt(error, f),
+            Self::Zip(error) => fmt::Display::fmt(error, f),
         }
     }
 }
@@ -27,30 +59,90 @@ impl error::Error for Error {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match self {
             Self::IO(error) => Some(error),
-            Self::CSV(error) => Some(error),
-            Self::BinanceClient(error) => Some(error),
+            Self::Api(..) => None,
+            Self::Arrow(error) => Some(error),
+            Self::Parquet(error) => Some(error),
+            Self::Csv(error) => Some(error),
+            Self::Checksum(_) => None,
+            Self::Config(_) => None,
+            Self::Connection(error) => Some(error),
             Self::Diesel(error) => Some(error),
+            Self::Json(error) => Some(error),
+            Self::Locked(_) => None,
+            Self::Migration(error) => Some(error.as_ref()),
             Self::ParseStr(_) => None,
+            Self::Pool(error) => Some(error),
+            Self::RateLimited(_) => None,
+            Self::Replay(_) => None,
+            Self::Request(error) => Some(error),
+            Self::Toml(error) => Some(error),
             Self::TryFromNumber(error) => Some(error),
+            Self::WebSocket(error) => Some(error.as_ref()),
+            Self::Zip(error) => Some(error),
         }
     }
 }
 
+impl Error {
+    /// Failures of a request to Binance, which only affect that query.
+    pub fn is_remote(&self) -> bool {
+        matches!(
+            self,
+            Self::Api(..) | Self::RateLimited(_) | Self::Request(_) | Self::WebSocket(_)
+        )
+    }
+
+    /// Remote failures that may succeed when repeated: timeouts, dropped
+    /// connections, server errors and rate limits. Rejected parameters and
+    /// unexpected responses are fatal.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Api(status, _) => *status >= 500,
+            Self::RateLimited(_) => true,
+            Self::Request(error) => is_transient(error),
+            Self::WebSocket(_) => true,
+            _ => false,
+        }
+    }
+}
+
+fn is_transient(error: &reqwest::Error) -> bool {
+    error.is_timeout()
+        || error.is_connect()
+        || error.is_request()
+        || error.is_body()
+        || error
+            .status()
+            .is_some_and(|status| status.is_server_error())
+}
+
 impl From<io::Error> for Error {
     fn from(error: io::Error) -> Self {
         Self::IO(error)
     }
 }
 
+impl From<arrow::error::ArrowError> for Error {
+    fn from(error: arrow::error::ArrowError) -> Self {
+        Self::Arrow(error)
+    }
+}
+
+impl From<parquet::errors::ParquetError> for Error {
+    fn from(error: parquet::errors::ParquetError) -> Self {
+        Self::Parquet(error)
+    }
+}
+
 impl From<csv::Error> for Error {
     fn from(error: csv::Error) -> Self {
-        Self::CSV(error)
+        Self::Csv(error)
     }
 }
 
-impl From<binance_client::errors::Error> for Error {
-    fn from(error: binance_client::errors::Error) -> Self {
-        Self::BinanceClient(error)
+impl From<diesel::ConnectionError> for Error {
+    fn from(error: diesel::ConnectionError) -> Self {
+        Self::Connection(error)
     }
 }
 
@@ -60,10 +152,46 @@ impl From<diesel::result::Error> for Error {
     }
 }
 
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Self::Json(error)
+    }
+}
+
+impl From<diesel::r2d2::PoolError> for Error {
+    fn from(error: diesel::r2d2::PoolError) -> Self {
+        Self::Pool(error)
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(error: reqwest::Error) -> Self {
+        Self::Request(error)
+    }
+}
+
+impl From<toml::de::Error> for Error {
+    fn from(error: toml::de::Error) -> Self {
+        Self::Toml(error)
+    }
+}
+
 impl From<num::TryFromIntError> for Error {
     fn from(error: num::TryFromIntError) -> Self {
         Self::TryFromNumber(error)
     }
 }
 
+impl From<tokio_tungstenite::tungstenite::Error> for Error {
+    fn from(error: tokio_tungstenite::tungstenite::Error) -> Self {
+        Self::WebSocket(Box::new(error))
+    }
+}
+
+impl From<zip::result::ZipError> for Error {
+    fn from(error: zip::result::ZipError) -> Self {
+        Self::Zip(error)
+    }
+}
+
 pub type Result<T = ()> = result::Result<T, Error>;
@@ -0,0 +1,102 @@
+use crate::result::Result;
+use log::warn;
+use rand::Rng;
+use std::{cmp, future::Future, time::Duration};
+
+/// Retries of a request failing with a transient error.
+pub const DEFAULT_RETRIES: u32 = 3;
+
+const BASE_DELAY: Duration = Duration::from_millis(500);
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Repeats transient failures with exponentially growing, jittered delays so
+/// concurrent clients do not retry in lockstep.
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    retries: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(DEFAULT_RETRIES)
+    }
+}
+
+impl Backoff {
+    pub fn new(retries: u32) -> Self {
+        Self { retries }
+    }
+
+    /// Delay before retry `attempt`, counting from zero: a random duration
+    /// between half and all of `BASE_DELAY * 2^attempt`, capped at `MAX_DELAY`.
+    fn delay(&self, attempt: u32) -> Duration {
+        let ceiling = cmp::min(BASE_DELAY.saturating_mul(1 << attempt.min(16)), MAX_DELAY);
+        rand::thread_rng().gen_range(ceiling / 2..=ceiling)
+    }
+
+    /// Runs `f` until it succeeds, fails permanently or the retries are used
+    /// up, returning the last result.
+    pub async fn retry<T, F, R>(&self, mut f: F) -> Result<T>
+    where
+        F: FnMut() -> R,
+        R: Future<Output = Result<T>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match f().await {
+                Err(error) if error.is_retryable() && attempt < self.retries => {
+                    let delay = self.delay(attempt);
+                    attempt += 1;
+                    warn!(
+                        "{}, retry {} of {} in {:?}",
+                        error, attempt, self.retries, delay
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Backoff, MAX_DELAY};
+    use crate::result::Error;
+    use std::time::Duration;
+
+    #[test]
+    fn grow_delays_with_jitter() {
+        let backoff = Backoff::new(3);
+        for _ in 0..100 {
+            let delay = backoff.delay(2);
+            assert!(delay >= Duration::from_secs(1) && delay <= Duration::from_secs(2));
+        }
+        assert!(backoff.delay(40) <= MAX_DELAY);
+    }
+
+    #[tokio::test]
+    async fn retry_transient_errors_only() {
+        let backoff = Backoff::new(1);
+
+        let mut calls = 0;
+        let result: Result<(), _> = backoff
+            .retry(|| {
+                calls += 1;
+                async { Err(Error::Api(503, "unavailable".into())) }
+            })
+            .await;
+        assert!(matches!(result, Err(Error::Api(503, _))));
+        assert_eq!(calls, 2);
+
+        let mut calls = 0;
+        let result: Result<(), _> = backoff
+            .retry(|| {
+                calls += 1;
+                async { Err(Error::Api(400, "-1121 Invalid symbol.".into())) }
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+}
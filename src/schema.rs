@@ -1,3 +1,96 @@
+table! {
+    use diesel::sql_types::*;
+
+    beholder_runs (id) {
+        id -> Varchar,
+        command -> Varchar,
+        arguments -> Text,
+        config_hash -> Nullable<Varchar>,
+        status -> Varchar,
+        rows_written -> Int8,
+        errors -> Nullable<Text>,
+        error -> Nullable<Text>,
+        started_at -> Timestamp,
+        finished_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::binance::Market;
+
+    beholder_tasks (id) {
+        id -> Int8,
+        job -> Varchar,
+        dataset -> Varchar,
+        source -> Market,
+        symbol -> Varchar,
+        interval -> Varchar,
+        row_limit -> Int4,
+        start_time -> Int8,
+        end_time -> Int8,
+        validate -> Bool,
+        audit -> Bool,
+        status -> Varchar,
+        attempts -> Int4,
+        max_attempts -> Int4,
+        available_at -> Timestamp,
+        run_id -> Nullable<Varchar>,
+        error -> Nullable<Text>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::binance::Market;
+
+    binance_agg_trades (symbol, source, agg_trade_id) {
+        symbol -> Varchar,
+        source -> Market,
+        agg_trade_id -> Int8,
+        price -> Text,
+        quantity -> Text,
+        first_trade_id -> Int8,
+        last_trade_id -> Int8,
+        transact_time -> Int8,
+        is_buyer_maker -> Bool,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+
+    binance_funding_rates (symbol, funding_time) {
+        symbol -> Varchar,
+        funding_time -> Int8,
+        funding_rate -> Text,
+        mark_price -> Nullable<Text>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        run_id -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::binance::Market;
+
+    binance_kline_revisions (id) {
+        id -> Int8,
+        symbol -> Varchar,
+        open_time -> Int8,
+        close_time -> Int8,
+        source -> Market,
+        old_values -> Text,
+        new_values -> Text,
+        run_id -> Varchar,
+        changed_at -> Timestamp,
+    }
+}
+
 table! {
     use diesel::sql_types::*;
     use crate::binance::Market;
@@ -18,6 +111,59 @@ table! {
         number_of_trades -> Int8,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        derived -> Bool,
+        run_id -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::binance::Market;
+
+    binance_klines_quarantine (symbol, open_time, close_time, source) {
+        symbol -> Varchar,
+        open_time -> Int8,
+        close_time -> Int8,
+        source -> Market,
+        open -> Text,
+        high -> Text,
+        low -> Text,
+        close -> Text,
+        base_volume -> Text,
+        quote_volume -> Text,
+        buy_base_volume -> Text,
+        buy_quote_volume -> Text,
+        number_of_trades -> Int8,
+        derived -> Bool,
+        rules -> Text,
+        quarantined_at -> Timestamp,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::binance::Market;
+
+    binance_local_klines (symbol, source, timezone, session_start, interval, local_date) {
+        symbol -> Varchar,
+        source -> Market,
+        timezone -> Varchar,
+        session_start -> Int8,
+        interval -> Varchar,
+        local_date -> Date,
+        open_time -> Int8,
+        close_time -> Int8,
+        open -> Text,
+        high -> Text,
+        low -> Text,
+        close -> Text,
+        base_volume -> Text,
+        quote_volume -> Text,
+        buy_base_volume -> Text,
+        buy_quote_volume -> Text,
+        number_of_trades -> Int8,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
     }
 }
 
@@ -32,10 +178,46 @@ table! {
         sum_open_interest_value -> Text,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        run_id -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::binance::Market;
+
+    derived_bars (symbol, source, bar_type, size, first_agg_trade_id) {
+        symbol -> Varchar,
+        source -> Market,
+        bar_type -> Varchar,
+        size -> Varchar,
+        first_agg_trade_id -> Int8,
+        last_agg_trade_id -> Int8,
+        open_time -> Int8,
+        close_time -> Int8,
+        open -> Text,
+        high -> Text,
+        low -> Text,
+        close -> Text,
+        base_volume -> Text,
+        quote_volume -> Text,
+        buy_base_volume -> Text,
+        buy_quote_volume -> Text,
+        number_of_trades -> Int8,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
     }
 }
 
 allow_tables_to_appear_in_same_query!(
+    beholder_runs,
+    beholder_tasks,
+    binance_agg_trades,
+    binance_funding_rates,
+    binance_kline_revisions,
     binance_klines,
+    binance_klines_quarantine,
+    binance_local_klines,
     binance_open_interest_summaries,
+    derived_bars,
 );
@@ -0,0 +1,283 @@
+use crate::binance::{interval_to_millis, millis_to_interval, Market, MarketEndpoint};
+use crate::database::DbConnection;
+use crate::export::write_table;
+use crate::result::Result;
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Nullable, Text};
+use serde::Serialize;
+use std::{collections::BTreeMap, io::Write};
+
+/// Rows grouped by series and by the distance to the previous row, so gaps
+/// are counted by the database without loading the rows.
+const KLINE_STEPS: &str = "SELECT source, symbol, length, step, COUNT(*) AS count, \
+    MIN(open_time) AS first_time, MAX(open_time) AS last_time, MAX(close_time) + 1 AS end_time \
+    FROM (SELECT source, symbol, open_time, close_time, close_time - open_time + 1 AS length, \
+    open_time - LAG(open_time) OVER ( \
+    PARTITION BY source, symbol, close_time - open_time ORDER BY open_time) AS step \
+    FROM binance_klines) AS steps \
+    GROUP BY source, symbol, length, step";
+
+const OPEN_INTEREST_STEPS: &str = "SELECT symbol, interval, step, COUNT(*) AS count, \
+    MIN(timestamp) AS first_time, MAX(timestamp) AS last_time, MAX(timestamp) AS end_time \
+    FROM (SELECT symbol, interval, timestamp, timestamp - LAG(timestamp) OVER ( \
+    PARTITION BY symbol, interval ORDER BY timestamp) AS step \
+    FROM binance_open_interest_summaries) AS steps \
+    GROUP BY symbol, interval, step";
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ArgEnum)]
+pub enum StatusFormat {
+    Table,
+    Json,
+}
+
+#[derive(QueryableByName)]
+struct KlineSteps {
+    #[diesel(sql_type = Market)]
+    source: MarketEndpoint,
+    #[diesel(sql_type = Text)]
+    symbol: String,
+    #[diesel(sql_type = BigInt)]
+    length: i64,
+    #[diesel(sql_type = Nullable<BigInt>)]
+    step: Option<i64>,
+    #[diesel(sql_type = BigInt)]
+    count: i64,
+    #[diesel(sql_type = BigInt)]
+    first_time: i64,
+    #[diesel(sql_type = BigInt)]
+    last_time: i64,
+    #[diesel(sql_type = BigInt)]
+    end_time: i64,
+}
+
+#[derive(QueryableByName)]
+struct OpenInterestSteps {
+    #[diesel(sql_type = Text)]
+    symbol: String,
+    #[diesel(sql_type = Text)]
+    interval: String,
+    #[diesel(sql_type = Nullable<BigInt>)]
+    step: Option<i64>,
+    #[diesel(sql_type = BigInt)]
+    count: i64,
+    #[diesel(sql_type = BigInt)]
+    first_time: i64,
+    #[diesel(sql_type = BigInt)]
+    last_time: i64,
+    #[diesel(sql_type = BigInt)]
+    end_time: i64,
+}
+
+/// Rows of one series that are `step` milliseconds after their predecessor,
+/// `None` for the first row.
+#[derive(Debug)]
+struct Steps {
+    series: (&'static str, String, String, String),
+    length: i64,
+    step: Option<i64>,
+    count: i64,
+    first_time: i64,
+    last_time: i64,
+    end_time: i64,
+}
+
+/// Coverage and freshness of one stored series.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct SeriesStatus {
+    pub dataset: &'static str,
+    pub source: String,
+    pub symbol: String,
+    pub interval: String,
+    pub first: DateTime<Utc>,
+    pub last: DateTime<Utc>,
+    pub rows: i64,
+    /// Rows between `first` and `last` without gaps
+    pub expected: i64,
+    /// Missing stretches of one or more rows
+    pub gaps: i64,
+    /// Seconds since the end of the newest row
+    pub age: i64,
+}
+
+/// Status of every kline and open interest series, ordered by dataset, source,
+/// symbol and interval.
+pub fn status(connection: &mut DbConnection, now: DateTime<Utc>) -> Result<Vec<SeriesStatus>> {
+    let klines = diesel::sql_query(KLINE_STEPS)
+        .load::<KlineSteps>(connection)?
+        .into_iter()
+        .map(|steps| Steps {
+            series: (
+                "klines",
+                steps.source.to_string(),
+                steps.symbol,
+                millis_to_interval(steps.length),
+            ),
+            length: steps.length,
+            step: steps.step,
+            count: steps.count,
+            first_time: steps.first_time,
+            last_time: steps.last_time,
+            end_time: steps.end_time,
+        });
+    let open_interest = diesel::sql_query(OPEN_INTEREST_STEPS)
+        .load::<OpenInterestSteps>(connection)?
+        .into_iter()
+        .map(|steps| {
+            Ok(Steps {
+                length: interval_to_millis(&steps.interval)?,
+                series: (
+                    "open-interest",
+                    MarketEndpoint::USDM.to_string(),
+                    steps.symbol,
+                    steps.interval,
+                ),
+                step: steps.step,
+                count: steps.count,
+                first_time: steps.first_time,
+                last_time: steps.last_time,
+                end_time: steps.end_time,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(summarize(klines.chain(open_interest), now))
+}
+
+fn summarize<I>(steps: I, now: DateTime<Utc>) -> Vec<SeriesStatus>
+where
+    I: IntoIterator<Item = Steps>,
+{
+    let mut series: BTreeMap<_, Vec<Steps>> = BTreeMap::new();
+    for steps in steps {
+        series.entry(steps.series.clone()).or_default().push(steps);
+    }
+    series
+        .into_iter()
+        .map(|((dataset, source, symbol, interval), steps)| {
+            let length = steps[0].length;
+            let first = steps.iter().map(|steps| steps.first_time).min().unwrap();
+            let last = steps.iter().map(|steps| steps.last_time).max().unwrap();
+            let end = steps.iter().map(|steps| steps.end_time).max().unwrap();
+            let gaps = steps
+                .iter()
+                .filter(|steps| steps.step.is_some_and(|step| step > length))
+                .map(|steps| steps.count)
+                .sum();
+            SeriesStatus {
+                dataset,
+                source,
+                symbol,
+                interval,
+                first: DateTime::from_timestamp_millis(first).unwrap_or_default(),
+                last: DateTime::from_timestamp_millis(last).unwrap_or_default(),
+                rows: steps.iter().map(|steps| steps.count).sum(),
+                expected: (last - first) / length + 1,
+                gaps,
+                age: (now.timestamp_millis() - end) / 1_000,
+            }
+        })
+        .collect()
+}
+
+/// A duration in seconds as its two largest units, such as `3d 4h`.
+fn format_age(seconds: i64) -> String {
+    if seconds < 0 {
+        return "0s".to_owned();
+    }
+    let units = [("d", 86_400), ("h", 3_600), ("m", 60), ("s", 1)];
+    let parts: Vec<String> = units
+        .iter()
+        .skip_while(|(_, unit)| seconds < *unit && *unit > 1)
+        .take(2)
+        .scan(seconds, |rest, (name, unit)| {
+            let count = *rest / unit;
+            *rest %= unit;
+            Some(format!("{}{}", count, name))
+        })
+        .collect();
+    parts.join(" ")
+}
+
+/// Prints `statuses` to stdout.
+pub fn print_status(statuses: &[SeriesStatus], format: StatusFormat) -> Result {
+    let mut output = std::io::stdout().lock();
+    match format {
+        StatusFormat::Json => {
+            serde_json::to_writer_pretty(&mut output, statuses)?;
+            writeln!(output)?;
+        }
+        StatusFormat::Table => {
+            let time = |time: &DateTime<Utc>| time.format("%Y-%m-%d %H:%M").to_string();
+            let rows: Vec<Vec<String>> = statuses
+                .iter()
+                .map(|status| {
+                    vec![
+                        status.dataset.to_owned(),
+                        status.source.to_owned(),
+                        status.symbol.to_owned(),
+                        status.interval.to_owned(),
+                        time(&status.first),
+                        time(&status.last),
+                        status.rows.to_string(),
+                        status.expected.to_string(),
+                        status.gaps.to_string(),
+                        format_age(status.age),
+                    ]
+                })
+                .collect();
+            let header = [
+                "DATASET", "SOURCE", "SYMBOL", "INTERVAL", "FIRST", "LAST", "ROWS", "EXPECTED",
+                "GAPS", "AGE",
+            ];
+            write_table(&mut output, &header, &rows)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_age, summarize, Steps};
+    use chrono::{DateTime, Utc};
+
+    fn steps(step: Option<i64>, count: i64, first_time: i64, last_time: i64) -> Steps {
+        Steps {
+            series: ("klines", "USDM".into(), "BTCUSDT".into(), "1m".into()),
+            length: 60_000,
+            step,
+            count,
+            first_time,
+            last_time,
+            end_time: last_time + 60_000,
+        }
+    }
+
+    #[test]
+    fn count_gaps_and_age() {
+        let start = 1704067200000;
+        // Ten minutes with the 4th and the 7th to 8th missing
+        let statuses = summarize(
+            [
+                steps(None, 1, start, start),
+                steps(Some(60_000), 5, start + 60_000, start + 540_000),
+                steps(Some(120_000), 1, start + 240_000, start + 240_000),
+                steps(Some(180_000), 1, start + 480_000, start + 480_000),
+            ],
+            DateTime::<Utc>::from_timestamp_millis(start + 3_600_000).unwrap(),
+        );
+
+        assert_eq!(statuses.len(), 1);
+        let status = &statuses[0];
+        assert_eq!(status.rows, 8);
+        assert_eq!(status.expected, 10);
+        assert_eq!(status.gaps, 2);
+        assert_eq!(status.age, 3_000);
+    }
+
+    #[test]
+    fn format_ages() {
+        assert_eq!(format_age(42), "42s");
+        assert_eq!(format_age(3_000), "50m 0s");
+        assert_eq!(format_age(273_600), "3d 4h");
+    }
+}
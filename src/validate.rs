@@ -0,0 +1,419 @@
+use crate::binance::{millis_to_interval, Kline, MarketEndpoint};
+use crate::database::DbConnection;
+use crate::export::{each_kline_batch, write_table, Filter};
+use crate::resample::{bucket_open_time, decimal};
+use crate::result::{Error, Result};
+use crate::schema::{binance_klines, binance_klines_quarantine};
+use chrono::{DateTime, Datelike, Months, Utc};
+use diesel::prelude::*;
+use log::warn;
+use rust_decimal::Decimal;
+use std::{collections::HashMap, fmt, io};
+
+const DAY: i64 = 86_400_000;
+
+/// Lengths of the Binance intervals from `1s` to `1w` whose klines open at
+/// multiples of their length. `3d` klines are not aligned to the epoch and
+/// `1M` klines follow the calendar.
+const ALIGNED_INTERVALS: [i64; 14] = [
+    1_000,
+    60_000,
+    180_000,
+    300_000,
+    900_000,
+    1_800_000,
+    3_600_000,
+    7_200_000,
+    14_400_000,
+    21_600_000,
+    28_800_000,
+    43_200_000,
+    DAY,
+    7 * DAY,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, clap::ArgEnum)]
+pub enum Rule {
+    /// Low and high bound open and close
+    Ohlc,
+    /// Volumes and trade counts are not negative
+    Volume,
+    /// Taker buy volumes do not exceed total volumes
+    TakerVolume,
+    /// Close time is open time plus a Binance interval minus one
+    CloseTime,
+    /// Klines of a series follow each other without overlapping
+    Monotonic,
+}
+
+impl Rule {
+    pub const ALL: [Self; 5] = [
+        Self::Ohlc,
+        Self::Volume,
+        Self::TakerVolume,
+        Self::CloseTime,
+        Self::Monotonic,
+    ];
+
+    /// Whether `kline` passes this rule on its own, `Monotonic` depends on
+    /// the previous kline and always passes here.
+    fn holds(&self, kline: &Kline) -> bool {
+        match self {
+            Self::Ohlc => {
+                let prices = [&kline.open, &kline.high, &kline.low, &kline.close]
+                    .map(|price| decimal(price).ok());
+                let [Some(open), Some(high), Some(low), Some(close)] = prices else {
+                    return false;
+                };
+                low >= Decimal::ZERO && low <= open.min(close) && high >= open.max(close)
+            }
+            Self::Volume => {
+                kline.number_of_trades >= 0
+                    && [
+                        &kline.base_volume,
+                        &kline.quote_volume,
+                        &kline.buy_base_volume,
+                        &kline.buy_quote_volume,
+                    ]
+                    .iter()
+                    .all(|volume| decimal(volume).is_ok_and(|volume| volume >= Decimal::ZERO))
+            }
+            Self::TakerVolume => {
+                let at_most = |part: &str, total: &str| matches!((decimal(part), decimal(total)), (Ok(part), Ok(total)) if part <= total);
+                at_most(&kline.buy_base_volume, &kline.base_volume)
+                    && at_most(&kline.buy_quote_volume, &kline.quote_volume)
+            }
+            Self::CloseTime => is_interval(kline.open_time, kline.close_time + 1),
+            Self::Monotonic => true,
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Self::Ohlc => "ohlc",
+            Self::Volume => "volume",
+            Self::TakerVolume => "taker-volume",
+            Self::CloseTime => "close-time",
+            Self::Monotonic => "monotonic",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Whether a kline from `open_time` until `end` spans one Binance interval.
+fn is_interval(open_time: i64, end: i64) -> bool {
+    let length = end - open_time;
+    if ALIGNED_INTERVALS.contains(&length) {
+        return bucket_open_time(open_time, length) == open_time;
+    }
+    if length == 3 * DAY {
+        return true;
+    }
+    // Monthly klines open at midnight of the first day of a month
+    let Some(open) = DateTime::<Utc>::from_timestamp_millis(open_time) else {
+        return false;
+    };
+    open.day() == 1
+        && open_time % DAY == 0
+        && open
+            .checked_add_months(Months::new(1))
+            .is_some_and(|next| next.timestamp_millis() == end)
+}
+
+/// A kline breaking one or more rules.
+#[derive(Debug, PartialEq)]
+pub struct Violation {
+    pub kline: Kline,
+    pub rules: Vec<Rule>,
+}
+
+/// Checks klines in time order, remembering where each series ended.
+#[derive(Debug)]
+pub struct Validator {
+    rules: Vec<Rule>,
+    ends: HashMap<(MarketEndpoint, String, i64), i64>,
+}
+
+impl Default for Validator {
+    fn default() -> Self {
+        Self::new(&Rule::ALL)
+    }
+}
+
+impl Validator {
+    pub fn new(rules: &[Rule]) -> Self {
+        Self {
+            rules: rules.to_vec(),
+            ends: HashMap::new(),
+        }
+    }
+
+    /// Rules broken by `kline`.
+    pub fn check(&mut self, kline: &Kline) -> Vec<Rule> {
+        let mut broken: Vec<Rule> = self
+            .rules
+            .iter()
+            .filter(|rule| !rule.holds(kline))
+            .copied()
+            .collect();
+        if self.rules.contains(&Rule::Monotonic) {
+            let series = (
+                kline.source,
+                kline.symbol.to_owned(),
+                kline.close_time - kline.open_time,
+            );
+            let end = self.ends.entry(series).or_insert(i64::MIN);
+            if kline.open_time <= *end {
+                broken.push(Rule::Monotonic);
+            }
+            *end = (*end).max(kline.close_time);
+        }
+        broken.sort_unstable();
+        broken
+    }
+
+    /// Splits `klines` into those passing every rule and the violations.
+    pub fn split(&mut self, klines: Vec<Kline>) -> (Vec<Kline>, Vec<Violation>) {
+        let mut valid = Vec::with_capacity(klines.len());
+        let mut violations = Vec::new();
+        for kline in klines {
+            let rules = self.check(&kline);
+            if rules.is_empty() {
+                valid.push(kline);
+            } else {
+                violations.push(Violation { kline, rules });
+            }
+        }
+        (valid, violations)
+    }
+}
+
+/// Checks the stored klines matching `filter` against `rules`.
+pub fn validate(
+    connection: &mut DbConnection,
+    filter: &Filter,
+    rules: &[Rule],
+) -> Result<Vec<Violation>> {
+    let mut validator = Validator::new(rules);
+    let mut violations = Vec::new();
+    each_kline_batch(connection, filter, |klines| {
+        for kline in klines {
+            let rules = validator.check(kline);
+            if !rules.is_empty() {
+                violations.push(Violation {
+                    kline: kline.clone(),
+                    rules,
+                });
+            }
+        }
+        Ok(())
+    })?;
+    Ok(violations)
+}
+
+/// A kline as kept in `binance_klines_quarantine`.
+#[derive(Insertable, AsChangeset)]
+#[diesel(table_name = binance_klines_quarantine)]
+struct QuarantinedKline<'a> {
+    symbol: &'a str,
+    open_time: i64,
+    close_time: i64,
+    source: MarketEndpoint,
+    open: &'a str,
+    high: &'a str,
+    low: &'a str,
+    close: &'a str,
+    base_volume: &'a str,
+    quote_volume: &'a str,
+    buy_base_volume: &'a str,
+    buy_quote_volume: &'a str,
+    number_of_trades: i64,
+    derived: bool,
+    rules: String,
+}
+
+impl<'a> From<&'a Violation> for QuarantinedKline<'a> {
+    fn from(violation: &'a Violation) -> Self {
+        let kline = &violation.kline;
+        Self {
+            symbol: &kline.symbol,
+            open_time: kline.open_time,
+            close_time: kline.close_time,
+            source: kline.source,
+            open: &kline.open,
+            high: &kline.high,
+            low: &kline.low,
+            close: &kline.close,
+            base_volume: &kline.base_volume,
+            quote_volume: &kline.quote_volume,
+            buy_base_volume: &kline.buy_base_volume,
+            buy_quote_volume: &kline.buy_quote_volume,
+            number_of_trades: kline.number_of_trades,
+            derived: kline.derived,
+            rules: rule_names(&violation.rules),
+        }
+    }
+}
+
+/// Saves `violations` into `binance_klines_quarantine`, replacing earlier
+/// copies.
+pub fn quarantine(violations: &[Violation], connection: &mut DbConnection) -> QueryResult<usize> {
+    violations
+        .iter()
+        .map(|violation| {
+            let row = QuarantinedKline::from(violation);
+            diesel::insert_into(binance_klines_quarantine::table)
+                .values(&row)
+                .on_conflict((
+                    binance_klines_quarantine::symbol,
+                    binance_klines_quarantine::open_time,
+                    binance_klines_quarantine::close_time,
+                    binance_klines_quarantine::source,
+                ))
+                .do_update()
+                .set(&row)
+                .execute(connection)
+        })
+        .sum()
+}
+
+/// Moves stored klines breaking a rule into `binance_klines_quarantine`.
+pub fn move_to_quarantine(violations: &[Violation], connection: &mut DbConnection) -> Result {
+    connection.transaction(|connection| {
+        quarantine(violations, connection)?;
+        for violation in violations {
+            let kline = &violation.kline;
+            diesel::delete(binance_klines::table.find((
+                &kline.symbol,
+                kline.open_time,
+                kline.close_time,
+                kline.source,
+            )))
+            .execute(connection)?;
+        }
+        Ok::<_, Error>(())
+    })
+}
+
+/// Quarantines fetched klines breaking a rule and returns the others, so
+/// they are checked before being upserted.
+pub fn quarantine_invalid(
+    mut klines: Vec<Kline>,
+    connection: &mut DbConnection,
+) -> Result<Vec<Kline>> {
+    klines.sort_by_key(|kline| kline.open_time);
+    let (valid, violations) = Validator::default().split(klines);
+    for violation in &violations {
+        warn!(
+            "Quarantined {} kline of {} at {} breaking {}",
+            violation.kline.source,
+            violation.kline.symbol,
+            violation.kline.open_time,
+            rule_names(&violation.rules)
+        );
+    }
+    quarantine(&violations, connection)?;
+    Ok(valid)
+}
+
+fn rule_names(rules: &[Rule]) -> String {
+    let names: Vec<String> = rules.iter().map(Rule::to_string).collect();
+    names.join(",")
+}
+
+/// Prints `violations` to stdout as a table.
+pub fn print_violations(violations: &[Violation]) -> Result {
+    let time = |time: i64| {
+        DateTime::<Utc>::from_timestamp_millis(time)
+            .unwrap_or_default()
+            .to_rfc3339()
+    };
+    let rows: Vec<Vec<String>> = violations
+        .iter()
+        .map(|violation| {
+            let kline = &violation.kline;
+            vec![
+                kline.source.to_string(),
+                kline.symbol.to_owned(),
+                millis_to_interval(kline.close_time - kline.open_time + 1),
+                time(kline.open_time),
+                kline.close_time.to_string(),
+                rule_names(&violation.rules),
+            ]
+        })
+        .collect();
+    let header = [
+        "SOURCE",
+        "SYMBOL",
+        "INTERVAL",
+        "OPEN_TIME",
+        "CLOSE_TIME",
+        "RULES",
+    ];
+    write_table(&mut io::stdout().lock(), &header, &rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Rule, Validator};
+    use crate::binance::{Kline, MarketEndpoint};
+
+    fn kline(open_time: i64, close_time: i64) -> Kline {
+        Kline {
+            source: MarketEndpoint::USDM,
+            symbol: "BTCUSDT".into(),
+            open_time,
+            close_time,
+            open: "42283.58".into(),
+            high: "42554.57".into(),
+            low: "42261.02".into(),
+            close: "42475.23".into(),
+            base_volume: "1271.68108".into(),
+            quote_volume: "53957635.9382665".into(),
+            buy_base_volume: "682.69811".into(),
+            buy_quote_volume: "28966644.141209".into(),
+            number_of_trades: 47134,
+            derived: false,
+        }
+    }
+
+    #[test]
+    fn check_each_rule() {
+        let hour = 1704067200000;
+        let mut validator = Validator::default();
+        assert!(validator.check(&kline(hour, hour + 3_599_999)).is_empty());
+
+        let mut bad = kline(hour + 3_600_000, hour + 7_199_999);
+        bad.high = "42400".into();
+        bad.buy_base_volume = "1300".into();
+        bad.quote_volume = "-1".into();
+        assert_eq!(
+            validator.check(&bad),
+            vec![Rule::Ohlc, Rule::Volume, Rule::TakerVolume]
+        );
+
+        // Overlaps the previous hour and is not an interval of its own
+        assert_eq!(
+            validator.check(&kline(hour + 1_800_000, hour + 5_399_998)),
+            vec![Rule::CloseTime]
+        );
+        assert_eq!(
+            validator.check(&kline(hour + 1_800_000, hour + 5_399_999)),
+            vec![Rule::CloseTime, Rule::Monotonic]
+        );
+    }
+
+    #[test]
+    fn accept_calendar_months() {
+        let mut validator = Validator::new(&[Rule::CloseTime]);
+        // February 2024 has 29 days
+        assert!(validator
+            .check(&kline(1706745600000, 1709251199999))
+            .is_empty());
+        assert!(!validator
+            .check(&kline(1706745600000, 1709337599999))
+            .is_empty());
+    }
+}
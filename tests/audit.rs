@@ -0,0 +1,53 @@
+mod common;
+
+use common::{beholder, MockBinance, TestDatabase};
+
+fn snapshot_klines(mock: &MockBinance, database: &TestDatabase, audit: bool) -> bool {
+    let mut arguments = vec![
+        "snapshot",
+        "--database-url",
+        &database.url,
+        "--auto-migrate",
+        "--api-base-url",
+        &mock.api_url,
+        "binance",
+        "kline",
+        "--market",
+        "usdm",
+        "--csv",
+        "tests/assets/binance/queries.csv",
+        "--from",
+        "2024-01-01T00:00:00Z",
+    ];
+    if audit {
+        arguments.push("--audit");
+    }
+    beholder(&arguments).status.success()
+}
+
+#[test]
+fn audit_records_revised_klines() {
+    let Some(database) = TestDatabase::create("audit") else {
+        return;
+    };
+    let mock = MockBinance::start();
+    mock.serve_klines("tests/assets/binance/klines_BTCUSDT_1m.json");
+
+    // New and unchanged klines are no revisions
+    assert!(snapshot_klines(&mock, &database, true));
+    assert!(snapshot_klines(&mock, &database, true));
+    assert!(database.revisions().is_empty());
+
+    mock.amend_kline(1704067440000, 4, "42311.00");
+    assert!(snapshot_klines(&mock, &database, true));
+    let revisions = database.revisions();
+    assert_eq!(revisions.len(), 1);
+    assert!(revisions[0].0.starts_with(r#"{"close":"#));
+    assert_eq!(revisions[0].1, r#"{"close":"42311.00"}"#);
+
+    // Without auditing changes go unrecorded
+    mock.amend_kline(1704067440000, 4, "42312.00");
+    assert!(snapshot_klines(&mock, &database, false));
+    assert_eq!(database.revisions().len(), 1);
+    assert_eq!(database.closes()[4], "42312.00");
+}
@@ -0,0 +1,387 @@
+//! An in-process stand-in for the Binance APIs serving recorded payloads, and
+//! throwaway databases for the `beholder` binary to write into.
+
+#![allow(dead_code)]
+
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Nullable, Text};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::Value;
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+    path::Path,
+    process::{Command, Output},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    runtime::Runtime,
+};
+use tokio_tungstenite::tungstenite::Message;
+
+#[cfg(not(feature = "sqlite"))]
+type DbConnection = diesel::PgConnection;
+#[cfg(feature = "sqlite")]
+type DbConnection = diesel::SqliteConnection;
+
+#[derive(Default)]
+struct State {
+    /// Rows of `/api/v3/klines` and `/fapi/v1/klines`
+    klines: Mutex<Vec<Value>>,
+    /// Rows of `/futures/data/openInterestHist`
+    open_interest: Mutex<Vec<Value>>,
+    /// Upcoming requests answered with 503
+    failures: AtomicUsize,
+    /// Targets of every request received
+    requests: Mutex<Vec<String>>,
+    /// Messages sent on each websocket connection, all but the last one are
+    /// closed afterwards
+    sessions: Mutex<VecDeque<Vec<String>>>,
+    connections: AtomicUsize,
+}
+
+pub struct MockBinance {
+    pub api_url: String,
+    pub ws_url: String,
+    state: Arc<State>,
+    _runtime: Runtime,
+}
+
+impl MockBinance {
+    pub fn start() -> Self {
+        let runtime = Runtime::new().unwrap();
+        let state = Arc::new(State::default());
+        let (api, ws) = runtime.block_on(async {
+            (
+                TcpListener::bind("127.0.0.1:0").await.unwrap(),
+                TcpListener::bind("127.0.0.1:0").await.unwrap(),
+            )
+        });
+        let api_url = format!("http://{}", api.local_addr().unwrap());
+        let ws_url = format!("ws://{}", ws.local_addr().unwrap());
+
+        let api_state = state.clone();
+        runtime.spawn(async move {
+            while let Ok((stream, _)) = api.accept().await {
+                tokio::spawn(serve_http(stream, api_state.clone()));
+            }
+        });
+        let ws_state = state.clone();
+        runtime.spawn(async move {
+            while let Ok((stream, _)) = ws.accept().await {
+                tokio::spawn(serve_ws(stream, ws_state.clone()));
+            }
+        });
+
+        Self {
+            api_url,
+            ws_url,
+            state,
+            _runtime: runtime,
+        }
+    }
+
+    /// Serves the klines of a recorded JSON response.
+    pub fn serve_klines<P>(&self, path: P)
+    where
+        P: AsRef<Path>,
+    {
+        let klines = serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        *self.state.klines.lock().unwrap() = klines;
+    }
+
+    /// Replaces field `index` of the kline opened at `open_time`.
+    /// Serves open interest summaries of `symbol` every five minutes from
+    /// `start`.
+    pub fn serve_open_interest(&self, symbol: &str, start: i64, count: usize) {
+        let rows = (0..count as i64)
+            .map(|index| {
+                serde_json::json!({
+                    "symbol": symbol,
+                    "sumOpenInterest": format!("{}.0", 20_000 + index),
+                    "sumOpenInterestValue": format!("{}.0", 150_000_000 + index),
+                    "timestamp": start + index * 300_000,
+                })
+            })
+            .collect();
+        *self.state.open_interest.lock().unwrap() = rows;
+    }
+
+    pub fn amend_kline(&self, open_time: i64, index: usize, value: &str) {
+        let mut klines = self.state.klines.lock().unwrap();
+        for kline in klines.iter_mut() {
+            if kline[0].as_i64() == Some(open_time) {
+                kline[index] = Value::from(value);
+            }
+        }
+    }
+
+    /// Answers the next `count` requests with 503 Service Unavailable.
+    pub fn fail_next(&self, count: usize) {
+        self.state.failures.store(count, Ordering::SeqCst);
+    }
+
+    pub fn requests(&self) -> Vec<String> {
+        self.state.requests.lock().unwrap().clone()
+    }
+
+    /// Adds a websocket connection sending `messages`.
+    pub fn add_session(&self, messages: Vec<String>) {
+        self.state.sessions.lock().unwrap().push_back(messages);
+    }
+
+    pub fn connections(&self) -> usize {
+        self.state.connections.load(Ordering::SeqCst)
+    }
+}
+
+async fn serve_http(mut stream: TcpStream, state: Arc<State>) {
+    let mut head = Vec::new();
+    let mut buffer = [0; 1024];
+    while !head.ends_with(b"\r\n\r\n") {
+        match stream.read(&mut buffer).await {
+            Ok(0) | Err(_) => return,
+            Ok(read) => head.extend_from_slice(&buffer[..read]),
+        }
+    }
+    let head = String::from_utf8_lossy(&head);
+    let target = head.split(' ').nth(1).unwrap_or_default().to_owned();
+    state.requests.lock().unwrap().push(target.to_owned());
+
+    let (status, body) = respond(&target, &state);
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nX-MBX-USED-WEIGHT-1M: 1\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+}
+
+fn respond(target: &str, state: &State) -> (&'static str, String) {
+    let failures = &state.failures;
+    if failures
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+            count.checked_sub(1)
+        })
+        .is_ok()
+    {
+        return ("503 Service Unavailable", String::new());
+    }
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let parameters: HashMap<&str, &str> = query
+        .split('&')
+        .filter_map(|parameter| parameter.split_once('='))
+        .collect();
+    let number = |name| {
+        parameters
+            .get(name)
+            .and_then(|value| value.parse::<i64>().ok())
+    };
+
+    let (rows, time, default_limit): (_, fn(&Value) -> i64, _) = if path.ends_with("/klines") {
+        (&state.klines, |kline| kline[0].as_i64().unwrap(), 500)
+    } else if path.ends_with("/openInterestHist") {
+        (
+            &state.open_interest,
+            |summary| summary["timestamp"].as_i64().unwrap(),
+            30,
+        )
+    } else {
+        let error = r#"{"code":-1121,"msg":"Invalid symbol."}"#;
+        return ("400 Bad Request", error.to_owned());
+    };
+    let rows = rows.lock().unwrap();
+    let limit = number("limit").unwrap_or(default_limit) as usize;
+    let selected: Vec<&Value> = match number("startTime") {
+        Some(start_time) => rows
+            .iter()
+            .filter(|row| time(row) >= start_time)
+            .filter(|row| number("endTime").is_none_or(|end| time(row) <= end))
+            .take(limit)
+            .collect(),
+        None => rows.iter().skip(rows.len().saturating_sub(limit)).collect(),
+    };
+    ("200 OK", serde_json::to_string(&selected).unwrap())
+}
+
+async fn serve_ws(stream: TcpStream, state: Arc<State>) {
+    let mut socket = match tokio_tungstenite::accept_async(stream).await {
+        Ok(socket) => socket,
+        Err(_) => return,
+    };
+    state.connections.fetch_add(1, Ordering::SeqCst);
+    let (messages, last) = {
+        let mut sessions = state.sessions.lock().unwrap();
+        let messages = sessions.pop_front().unwrap_or_default();
+        (messages, sessions.is_empty())
+    };
+    for message in messages {
+        if socket.send(Message::Text(message)).await.is_err() {
+            return;
+        }
+    }
+    if last {
+        while let Some(Ok(_)) = socket.next().await {}
+    } else {
+        let _ = socket.close(None).await;
+    }
+}
+
+/// A database created for one test and dropped afterwards.
+pub struct TestDatabase {
+    pub url: String,
+    #[cfg(not(feature = "sqlite"))]
+    admin_url: String,
+    #[cfg(not(feature = "sqlite"))]
+    name: String,
+}
+
+impl TestDatabase {
+    /// An empty database on the server of `BEHOLDER_TEST_DATABASE_URL`, or
+    /// `None` when it is not set.
+    #[cfg(not(feature = "sqlite"))]
+    pub fn create(test: &str) -> Option<Self> {
+        let admin_url = match std::env::var("BEHOLDER_TEST_DATABASE_URL") {
+            Ok(url) => url,
+            Err(_) => {
+                eprintln!("BEHOLDER_TEST_DATABASE_URL is not set, skipping {}", test);
+                return None;
+            }
+        };
+        let name = format!("beholder_test_{}_{}", std::process::id(), test);
+        let mut admin = DbConnection::establish(&admin_url).unwrap();
+        diesel::sql_query(format!("CREATE DATABASE {}", name))
+            .execute(&mut admin)
+            .unwrap();
+        let (server, _) = admin_url.rsplit_once('/').unwrap();
+        Some(Self {
+            url: format!("{}/{}", server, name),
+            admin_url,
+            name,
+        })
+    }
+
+    /// A database file in the temporary directory.
+    #[cfg(feature = "sqlite")]
+    pub fn create(test: &str) -> Option<Self> {
+        let path = std::env::temp_dir().join(format!(
+            "beholder_test_{}_{}.sqlite",
+            std::process::id(),
+            test
+        ));
+        let _ = fs::remove_file(&path);
+        Some(Self {
+            url: path.to_string_lossy().into_owned(),
+        })
+    }
+
+    pub fn connect(&self) -> DbConnection {
+        DbConnection::establish(&self.url).unwrap()
+    }
+
+    pub fn count(&self, table: &str) -> i64 {
+        #[derive(QueryableByName)]
+        struct Count {
+            #[diesel(sql_type = BigInt)]
+            count: i64,
+        }
+        diesel::sql_query(format!("SELECT COUNT(*) AS count FROM {}", table))
+            .get_result::<Count>(&mut self.connect())
+            .unwrap()
+            .count
+    }
+
+    /// Close prices of stored klines ordered by open time.
+    pub fn closes(&self) -> Vec<String> {
+        #[derive(QueryableByName)]
+        struct Close {
+            #[diesel(sql_type = Text)]
+            close: String,
+        }
+        diesel::sql_query("SELECT close FROM binance_klines ORDER BY open_time")
+            .load::<Close>(&mut self.connect())
+            .unwrap()
+            .into_iter()
+            .map(|row| row.close)
+            .collect()
+    }
+
+    /// Old and new values of recorded kline revisions in insertion order.
+    pub fn revisions(&self) -> Vec<(String, String)> {
+        #[derive(QueryableByName)]
+        struct Revision {
+            #[diesel(sql_type = Text)]
+            old_values: String,
+            #[diesel(sql_type = Text)]
+            new_values: String,
+        }
+        diesel::sql_query("SELECT old_values, new_values FROM binance_kline_revisions ORDER BY id")
+            .load::<Revision>(&mut self.connect())
+            .unwrap()
+            .into_iter()
+            .map(|row| (row.old_values, row.new_values))
+            .collect()
+    }
+
+    /// Recorded runs in the order they started.
+    pub fn runs(&self) -> Vec<Run> {
+        diesel::sql_query(
+            "SELECT id, command, status, rows_written, errors, error FROM beholder_runs \
+             ORDER BY started_at, finished_at",
+        )
+        .load::<Run>(&mut self.connect())
+        .unwrap()
+    }
+}
+
+#[derive(Debug, QueryableByName)]
+pub struct Run {
+    #[diesel(sql_type = Text)]
+    pub id: String,
+    #[diesel(sql_type = Text)]
+    pub command: String,
+    #[diesel(sql_type = Text)]
+    pub status: String,
+    #[diesel(sql_type = BigInt)]
+    pub rows_written: i64,
+    #[diesel(sql_type = Nullable<Text>)]
+    pub errors: Option<String>,
+    #[diesel(sql_type = Nullable<Text>)]
+    pub error: Option<String>,
+}
+
+impl Drop for TestDatabase {
+    #[cfg(not(feature = "sqlite"))]
+    fn drop(&mut self) {
+        if let Ok(mut admin) = DbConnection::establish(&self.admin_url) {
+            let _ = diesel::sql_query(format!(
+                "DROP DATABASE IF EXISTS {} WITH (FORCE)",
+                self.name
+            ))
+            .execute(&mut admin);
+        }
+    }
+
+    #[cfg(feature = "sqlite")]
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.url);
+    }
+}
+
+/// Runs the `beholder` binary to completion.
+pub fn beholder(arguments: &[&str]) -> Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_beholder"))
+        .arg("-vvv")
+        .args(arguments)
+        .output()
+        .unwrap();
+    eprintln!("{}", String::from_utf8_lossy(&output.stderr));
+    output
+}
@@ -0,0 +1,54 @@
+mod common;
+
+use common::{beholder, MockBinance, TestDatabase};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn runs(database: &TestDatabase, status: &str) -> i64 {
+    database.count(&format!(
+        "beholder_runs WHERE command = 'daemon' AND status = '{}'",
+        status
+    ))
+}
+
+#[test]
+fn daemon_runs_jobs_concurrently_on_one_connection() {
+    let Some(database) = TestDatabase::create("daemon") else {
+        return;
+    };
+    assert!(beholder(&["migrate", "--database-url", &database.url, "up"])
+        .status
+        .success());
+    let mock = MockBinance::start();
+    mock.serve_klines("tests/assets/binance/klines_BTCUSDT_1m.json");
+
+    // Locks take connections of their own, so both jobs fit one pooled
+    // connection
+    let mut daemon = Command::new(env!("CARGO_BIN_EXE_beholder"))
+        .args([
+            "daemon",
+            "--database-url",
+            &database.url,
+            "--config",
+            "tests/assets/daemon.toml",
+            "--api-base-url",
+            &mock.api_url,
+            "--pool-size",
+            "1",
+        ])
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(20);
+    while runs(&database, "succeeded") < 4 && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(200));
+    }
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+
+    assert!(runs(&database, "succeeded") >= 4);
+    assert_eq!(runs(&database, "failed"), 0);
+    assert_eq!(database.count("binance_klines"), 5);
+}
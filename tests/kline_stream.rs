@@ -0,0 +1,65 @@
+mod common;
+
+use common::{MockBinance, TestDatabase};
+use std::{
+    fs,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+#[test]
+fn kline_stream_reconnects_and_stores_final_klines() {
+    let Some(database) = TestDatabase::create("kline_stream") else {
+        return;
+    };
+    let mock = MockBinance::start();
+    let messages: Vec<String> = fs::read_to_string("tests/assets/binance/kline_stream.jsonl")
+        .unwrap()
+        .lines()
+        .map(str::to_owned)
+        .collect();
+    // The first connection closes after the first complete kline
+    mock.add_session(messages[..2].to_vec());
+    mock.add_session(messages[2..].to_vec());
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_beholder"))
+        .args([
+            "-vvv",
+            "snapshot",
+            "--database-url",
+            &database.url,
+            "--auto-migrate",
+            "--ws-base-url",
+            &mock.ws_url,
+            "binance",
+            "kline-stream",
+            "--market",
+            "usdm",
+            "--csv",
+            "tests/assets/binance/queries.csv",
+        ])
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(30);
+    let mut stored = 0;
+    while Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(200));
+        // Tables appear once the command migrated the database
+        if mock.connections() > 0 {
+            stored = database.count("binance_klines");
+            if stored == 2 {
+                break;
+            }
+        }
+    }
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    assert_eq!(stored, 2);
+    assert_eq!(mock.connections(), 2);
+    // Incomplete klines are not stored
+    assert_eq!(database.closes(), vec!["42283.58", "42300.00"]);
+}
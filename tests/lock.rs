@@ -0,0 +1,61 @@
+#![cfg(not(feature = "sqlite"))]
+
+mod common;
+
+use common::{beholder, MockBinance, TestDatabase};
+use diesel::prelude::*;
+use diesel::sql_types::BigInt;
+use sha2::{Digest, Sha256};
+use std::process::Output;
+
+fn snapshot_klines(mock: &MockBinance, database: &TestDatabase, on_locked: &str) -> Output {
+    beholder(&[
+        "snapshot",
+        "--database-url",
+        &database.url,
+        "--auto-migrate",
+        "--api-base-url",
+        &mock.api_url,
+        "--on-locked",
+        on_locked,
+        "binance",
+        "kline",
+        "--market",
+        "usdm",
+        "--csv",
+        "tests/assets/binance/queries.csv",
+        "--from",
+        "2024-01-01T00:00:00Z",
+    ])
+}
+
+/// The advisory lock key of the snapshot above.
+fn snapshot_key() -> i64 {
+    let csv = std::fs::canonicalize("tests/assets/binance/queries.csv").unwrap();
+    let identity = format!("snapshot binance kline USDM {}", csv.display());
+    let digest = Sha256::digest(identity.as_bytes());
+    i64::from_be_bytes(digest[..8].try_into().unwrap())
+}
+
+#[test]
+fn snapshot_skips_or_fails_while_locked() {
+    let Some(database) = TestDatabase::create("lock") else {
+        return;
+    };
+    let mock = MockBinance::start();
+    mock.serve_klines("tests/assets/binance/klines_BTCUSDT_1m.json");
+
+    let mut holder = database.connect();
+    diesel::sql_query("SELECT pg_advisory_lock($1)")
+        .bind::<BigInt, _>(snapshot_key())
+        .execute(&mut holder)
+        .unwrap();
+
+    assert!(snapshot_klines(&mock, &database, "skip").status.success());
+    assert!(!snapshot_klines(&mock, &database, "fail").status.success());
+    assert!(mock.requests().is_empty());
+
+    drop(holder);
+    assert!(snapshot_klines(&mock, &database, "fail").status.success());
+    assert_eq!(database.count("binance_klines"), 5);
+}
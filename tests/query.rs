@@ -0,0 +1,72 @@
+mod common;
+
+use common::{beholder, MockBinance, TestDatabase};
+use serde_json::Value;
+
+fn query(database: &TestDatabase, arguments: &[&str]) -> String {
+    let mut command = vec!["query", "--database-url", &database.url];
+    command.extend_from_slice(arguments);
+    let output = beholder(&command);
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn query_decodes_stored_klines() {
+    let Some(database) = TestDatabase::create("query") else {
+        return;
+    };
+    let mock = MockBinance::start();
+    mock.serve_klines("tests/assets/binance/klines_BTCUSDT_1m.json");
+    assert!(beholder(&[
+        "snapshot",
+        "--database-url",
+        &database.url,
+        "--auto-migrate",
+        "--api-base-url",
+        &mock.api_url,
+        "binance",
+        "kline",
+        "--market",
+        "usdm",
+        "--csv",
+        "tests/assets/binance/queries.csv",
+        "--from",
+        "2024-01-01T00:00:00Z",
+    ])
+    .status
+    .success());
+
+    let filter = [
+        "klines",
+        "--market",
+        "usdm",
+        "--symbol",
+        "BTCUSDT",
+        "--interval",
+        "1m",
+        "--from",
+        "2024-01-01T00:01:00Z",
+        "--to",
+        "2024-01-01T00:03:00Z",
+    ];
+
+    let table = query(&database, &filter);
+    let lines: Vec<&str> = table.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert!(lines[0].starts_with("symbol   source  open_time"));
+    assert!(lines[1].starts_with("BTCUSDT  USDM    2024-01-01T00:01:00Z"));
+
+    let csv = query(&database, &[&filter[..], &["--format", "csv"]].concat());
+    assert_eq!(csv.lines().count(), 4);
+
+    let json = query(&database, &[&filter[..], &["--format", "json"]].concat());
+    let rows: Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(rows.as_array().unwrap().len(), 3);
+    assert_eq!(rows[2]["open_time"], "2024-01-01T00:03:00Z");
+    assert_eq!(rows[2]["interval"], "1m");
+
+    // Nothing stored yet is an empty array, not an error
+    let json = query(&database, &["funding", "--format", "json"]);
+    assert_eq!(json.trim(), "[]");
+}
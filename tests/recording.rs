@@ -0,0 +1,143 @@
+mod common;
+
+use common::{beholder, MockBinance, TestDatabase};
+use std::{
+    fs,
+    path::Path,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+const QUERIES: &str = "tests/assets/binance/queries.csv";
+
+fn snapshot(
+    database: &TestDatabase,
+    recording: &[&str],
+    options: &[&str],
+    command: &[&str],
+) -> Command {
+    let mut beholder = Command::new(env!("CARGO_BIN_EXE_beholder"));
+    beholder
+        .arg("-vvv")
+        .args(recording)
+        .args([
+            "snapshot",
+            "--database-url",
+            &database.url,
+            "--auto-migrate",
+        ])
+        .args(options)
+        .args(command)
+        .args(["--market", "usdm", "--csv", QUERIES]);
+    beholder
+}
+
+fn recording_dir(test: &str) -> String {
+    let dir = std::env::temp_dir().join(format!("beholder_{}_{}", std::process::id(), test));
+    let _ = fs::remove_dir_all(&dir);
+    dir.to_string_lossy().into_owned()
+}
+
+#[test]
+fn replay_recorded_klines() {
+    let (Some(recorded), Some(replayed)) = (
+        TestDatabase::create("record_klines"),
+        TestDatabase::create("replay_klines"),
+    ) else {
+        return;
+    };
+    let dir = recording_dir("record_klines");
+    let mock = MockBinance::start();
+    mock.serve_klines("tests/assets/binance/klines_BTCUSDT_1m.json");
+    mock.fail_next(1);
+
+    let kline = ["binance", "kline", "--from", "2024-01-01T00:00:00Z"];
+    let api = ["--api-base-url", &mock.api_url];
+    let output = snapshot(&recorded, &["--record-dir", &dir], &api, &kline)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(Path::new(&dir).join("responses.jsonl").exists());
+    drop(mock);
+
+    // The failed request is replayed and retried as well
+    let output = snapshot(&replayed, &["--replay-dir", &dir], &[], &kline)
+        .output()
+        .unwrap();
+    eprintln!("{}", String::from_utf8_lossy(&output.stderr));
+    assert!(output.status.success());
+    assert_eq!(replayed.count("binance_klines"), 5);
+    assert_eq!(replayed.closes(), recorded.closes());
+
+    // Requests that were never recorded fail instead of reaching Binance
+    let output = beholder(&[
+        "--replay-dir",
+        &dir,
+        "snapshot",
+        "--database-url",
+        &replayed.url,
+        "binance",
+        "kline",
+        "--market",
+        "spot",
+        "--csv",
+        QUERIES,
+    ]);
+    assert!(!output.status.success());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn replay_recorded_kline_stream() {
+    let (Some(recorded), Some(replayed)) = (
+        TestDatabase::create("record_stream"),
+        TestDatabase::create("replay_stream"),
+    ) else {
+        return;
+    };
+    let dir = recording_dir("record_stream");
+    let mock = MockBinance::start();
+    let messages: Vec<String> = fs::read_to_string("tests/assets/binance/kline_stream.jsonl")
+        .unwrap()
+        .lines()
+        .map(str::to_owned)
+        .collect();
+    mock.add_session(messages[..2].to_vec());
+    mock.add_session(messages[2..].to_vec());
+
+    let mut child = snapshot(
+        &recorded,
+        &["--record-dir", &dir],
+        &["--ws-base-url", &mock.ws_url],
+        &["binance", "kline-stream"],
+    )
+    .stderr(Stdio::null())
+    .spawn()
+    .unwrap();
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(200));
+        if mock.connections() > 0 && recorded.count("binance_klines") == 2 {
+            break;
+        }
+    }
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    // Replay ends once every recorded connection was served
+    let output = snapshot(
+        &replayed,
+        &["--replay-dir", &dir],
+        &[],
+        &["binance", "kline-stream"],
+    )
+    .output()
+    .unwrap();
+    eprintln!("{}", String::from_utf8_lossy(&output.stderr));
+    assert!(output.status.success());
+    assert_eq!(replayed.closes(), vec!["42283.58", "42300.00"]);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,70 @@
+mod common;
+
+use common::{beholder, MockBinance, TestDatabase};
+
+const QUERIES: &str = "tests/assets/binance/queries.csv";
+
+fn snapshot_klines(mock: &MockBinance, database: &TestDatabase, retries: &str, csv: &str) -> bool {
+    beholder(&[
+        "snapshot",
+        "--database-url",
+        &database.url,
+        "--auto-migrate",
+        "--api-base-url",
+        &mock.api_url,
+        "--retries",
+        retries,
+        "binance",
+        "kline",
+        "--market",
+        "usdm",
+        "--csv",
+        csv,
+        "--from",
+        "2024-01-01T00:00:00Z",
+    ])
+    .status
+    .success()
+}
+
+#[test]
+fn snapshot_records_runs() {
+    let Some(database) = TestDatabase::create("runs") else {
+        return;
+    };
+    let mock = MockBinance::start();
+    mock.serve_klines("tests/assets/binance/klines_BTCUSDT_1m.json");
+
+    assert!(snapshot_klines(&mock, &database, "0", QUERIES));
+    let runs = database.runs();
+    assert_eq!(runs.len(), 1);
+    let run = &runs[0];
+    assert_eq!(run.command, "snapshot binance kline");
+    assert_eq!(run.status, "succeeded");
+    assert_eq!(run.rows_written, 5);
+    assert_eq!(run.errors, None);
+    assert_eq!(
+        database.count(&format!("binance_klines WHERE run_id = '{}'", run.id)),
+        5
+    );
+
+    // Failed queries fail the run and leave the rows to the previous one
+    mock.fail_next(1);
+    assert!(!snapshot_klines(&mock, &database, "0", QUERIES));
+    let runs = database.runs();
+    assert_eq!(runs.len(), 2);
+    let failed = runs.iter().find(|run| run.status == "failed").unwrap();
+    assert_eq!(failed.rows_written, 0);
+    assert!(failed.errors.as_deref().unwrap().contains("BTCUSDT"));
+    assert_eq!(
+        database.count(&format!("binance_klines WHERE run_id = '{}'", run.id)),
+        5
+    );
+
+    // Errors stopping the session are recorded before the command fails
+    assert!(!snapshot_klines(&mock, &database, "0", "missing.csv"));
+    let runs = database.runs();
+    assert_eq!(runs.len(), 3);
+    let stopped = runs.iter().find(|run| run.error.is_some()).unwrap();
+    assert_eq!(stopped.status, "failed");
+}
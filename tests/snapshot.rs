@@ -0,0 +1,158 @@
+mod common;
+
+use common::{beholder, MockBinance, TestDatabase};
+use std::net::TcpListener;
+use std::time::{Duration, Instant};
+
+const KLINES: &str = "tests/assets/binance/klines_BTCUSDT_1m.json";
+const QUERIES: &str = "tests/assets/binance/queries.csv";
+
+fn snapshot_klines(mock: &MockBinance, database: &TestDatabase, retries: &str) -> bool {
+    beholder(&[
+        "snapshot",
+        "--database-url",
+        &database.url,
+        "--auto-migrate",
+        "--api-base-url",
+        &mock.api_url,
+        "--retries",
+        retries,
+        "binance",
+        "kline",
+        "--market",
+        "usdm",
+        "--csv",
+        QUERIES,
+        "--from",
+        "2024-01-01T00:00:00Z",
+    ])
+    .status
+    .success()
+}
+
+#[test]
+fn snapshot_pages_through_klines() {
+    let Some(database) = TestDatabase::create("snapshot_pages") else {
+        return;
+    };
+    let mock = MockBinance::start();
+    mock.serve_klines(KLINES);
+
+    assert!(snapshot_klines(&mock, &database, "0"));
+
+    // Pages of the CSV limit of 2 until a short page
+    let requests = mock.requests();
+    assert_eq!(requests.len(), 3);
+    assert!(requests[0].starts_with("/fapi/v1/klines?symbol=BTCUSDT&interval=1m&limit=2"));
+    assert!(requests[0].ends_with("startTime=1704067200000"));
+    assert!(requests[2].ends_with("startTime=1704067380001"));
+    assert_eq!(database.count("binance_klines"), 5);
+}
+
+#[test]
+fn snapshot_pages_through_open_interest() {
+    let Some(database) = TestDatabase::create("snapshot_open_interest_pages") else {
+        return;
+    };
+    let mock = MockBinance::start();
+    mock.serve_open_interest("BTCUSDT", 1704067200000, 5);
+
+    let output = beholder(&[
+        "snapshot",
+        "--database-url",
+        &database.url,
+        "--auto-migrate",
+        "--api-base-url",
+        &mock.api_url,
+        "binance",
+        "open-interest-summary",
+        "--csv",
+        QUERIES,
+        "--interval",
+        "5m",
+        "--from",
+        "2024-01-01T00:00:00Z",
+    ]);
+    assert!(output.status.success());
+
+    // Pages of the CSV limit of 2 until a short page
+    let requests = mock.requests();
+    assert_eq!(requests.len(), 3);
+    assert!(
+        requests[0].starts_with("/futures/data/openInterestHist?symbol=BTCUSDT&period=5m&limit=2")
+    );
+    assert!(requests[2].ends_with("startTime=1704068100001"));
+    assert_eq!(database.count("binance_open_interest_summaries"), 5);
+}
+
+#[test]
+fn snapshot_upserts_idempotently() {
+    let Some(database) = TestDatabase::create("snapshot_upserts") else {
+        return;
+    };
+    let mock = MockBinance::start();
+    mock.serve_klines(KLINES);
+
+    assert!(snapshot_klines(&mock, &database, "0"));
+    assert!(snapshot_klines(&mock, &database, "0"));
+    assert_eq!(database.count("binance_klines"), 5);
+
+    // A revised kline replaces the stored one
+    mock.amend_kline(1704067440000, 4, "42311.00");
+    assert!(snapshot_klines(&mock, &database, "0"));
+    assert_eq!(database.count("binance_klines"), 5);
+    assert_eq!(database.closes()[4], "42311.00");
+}
+
+#[test]
+fn snapshot_retries_server_errors() {
+    let Some(database) = TestDatabase::create("snapshot_retries") else {
+        return;
+    };
+    let mock = MockBinance::start();
+    mock.serve_klines(KLINES);
+
+    mock.fail_next(2);
+    assert!(snapshot_klines(&mock, &database, "2"));
+    assert_eq!(mock.requests().len(), 5);
+    assert_eq!(database.count("binance_klines"), 5);
+
+    // Failing beyond the retries fails the query and the command
+    mock.fail_next(2);
+    assert!(!snapshot_klines(&mock, &database, "1"));
+}
+
+#[test]
+fn snapshot_times_out_stalled_requests() {
+    let Some(database) = TestDatabase::create("snapshot_timeout") else {
+        return;
+    };
+    // Accepts connections but never answers
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let api_url = format!("http://{}", listener.local_addr().unwrap());
+
+    let started = Instant::now();
+    let output = beholder(&[
+        "snapshot",
+        "--database-url",
+        &database.url,
+        "--auto-migrate",
+        "--api-base-url",
+        &api_url,
+        "--retries",
+        "1",
+        "--timeout",
+        "1",
+        "binance",
+        "kline",
+        "--market",
+        "usdm",
+        "--csv",
+        QUERIES,
+        "--from",
+        "2024-01-01T00:00:00Z",
+    ]);
+    assert!(!output.status.success());
+    assert!(started.elapsed() < Duration::from_secs(30));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("retry 1 of 1"));
+}
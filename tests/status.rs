@@ -0,0 +1,66 @@
+mod common;
+
+use common::{beholder, MockBinance, TestDatabase};
+use serde_json::Value;
+
+#[test]
+fn status_reports_coverage_and_staleness() {
+    let Some(database) = TestDatabase::create("status") else {
+        return;
+    };
+    let mock = MockBinance::start();
+    mock.serve_klines("tests/assets/binance/klines_BTCUSDT_1m.json");
+    assert!(beholder(&[
+        "snapshot",
+        "--database-url",
+        &database.url,
+        "--auto-migrate",
+        "--api-base-url",
+        &mock.api_url,
+        "binance",
+        "kline",
+        "--market",
+        "usdm",
+        "--csv",
+        "tests/assets/binance/queries.csv",
+        "--from",
+        "2024-01-01T00:00:00Z",
+    ])
+    .status
+    .success());
+
+    let output = beholder(&[
+        "status",
+        "--database-url",
+        &database.url,
+        "--format",
+        "json",
+    ]);
+    assert!(output.status.success());
+    let statuses: Value = serde_json::from_slice(&output.stdout).unwrap();
+    let status = &statuses[0];
+    assert_eq!(status["dataset"], "klines");
+    assert_eq!(status["source"], "USDM");
+    assert_eq!(status["symbol"], "BTCUSDT");
+    assert_eq!(status["interval"], "1m");
+    assert_eq!(status["first"], "2024-01-01T00:00:00Z");
+    assert_eq!(status["last"], "2024-01-01T00:04:00Z");
+    assert_eq!(status["rows"], 5);
+    assert_eq!(status["expected"], 5);
+    assert_eq!(status["gaps"], 0);
+
+    let output = beholder(&["status", "--database-url", &database.url]);
+    let table = String::from_utf8(output.stdout).unwrap();
+    assert!(table.starts_with("DATASET"));
+    assert!(table.contains("BTCUSDT"));
+
+    // The recorded klines are long past any threshold
+    let output = beholder(&[
+        "status",
+        "--database-url",
+        &database.url,
+        "--stale-after",
+        "1d",
+    ]);
+    assert!(!output.status.success());
+}
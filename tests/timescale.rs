@@ -0,0 +1,50 @@
+#![cfg(not(feature = "sqlite"))]
+
+mod common;
+
+use common::{beholder, MockBinance, TestDatabase};
+
+#[test]
+fn timescale_migrations_run_after_plain_ones() {
+    let Some(database) = TestDatabase::create("timescale") else {
+        return;
+    };
+    if database.count("pg_available_extensions WHERE name = 'timescaledb'") == 0 {
+        eprintln!("TimescaleDB is not installed, skipping timescale");
+        return;
+    }
+
+    // Columns and indexes of later plain migrations exist before the
+    // hypertables are created
+    assert!(beholder(&[
+        "migrate",
+        "--database-url",
+        &database.url,
+        "--timescale",
+        "up"
+    ])
+    .status
+    .success());
+    assert_eq!(database.count("timescaledb_information.hypertables"), 3);
+
+    let mock = MockBinance::start();
+    mock.serve_klines("tests/assets/binance/klines_BTCUSDT_1m.json");
+    assert!(beholder(&[
+        "snapshot",
+        "--database-url",
+        &database.url,
+        "--api-base-url",
+        &mock.api_url,
+        "binance",
+        "kline",
+        "--market",
+        "usdm",
+        "--csv",
+        "tests/assets/binance/queries.csv",
+        "--from",
+        "2024-01-01T00:00:00Z",
+    ])
+    .status
+    .success());
+    assert_eq!(database.count("binance_klines WHERE run_id IS NOT NULL"), 5);
+}
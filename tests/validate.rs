@@ -0,0 +1,72 @@
+mod common;
+
+use common::{beholder, MockBinance, TestDatabase};
+
+fn snapshot_klines(mock: &MockBinance, database: &TestDatabase, validate: bool) -> bool {
+    let mut arguments = vec![
+        "snapshot",
+        "--database-url",
+        &database.url,
+        "--auto-migrate",
+        "--api-base-url",
+        &mock.api_url,
+        "binance",
+        "kline",
+        "--market",
+        "usdm",
+        "--csv",
+        "tests/assets/binance/queries.csv",
+        "--from",
+        "2024-01-01T00:00:00Z",
+    ];
+    if validate {
+        arguments.push("--validate");
+    }
+    beholder(&arguments).status.success()
+}
+
+#[test]
+fn validate_reports_and_quarantines_klines() {
+    let Some(database) = TestDatabase::create("validate") else {
+        return;
+    };
+    let mock = MockBinance::start();
+    mock.serve_klines("tests/assets/binance/klines_BTCUSDT_1m.json");
+    // A high below the close
+    mock.amend_kline(1704067260000, 2, "42290.00");
+
+    assert!(snapshot_klines(&mock, &database, false));
+    assert_eq!(database.count("binance_klines"), 5);
+
+    let output = beholder(&["validate", "--database-url", &database.url]);
+    assert!(!output.status.success());
+    let report = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(report.lines().count(), 2);
+    assert!(report.contains("2024-01-01T00:01:00+00:00"));
+    assert!(report.contains("ohlc"));
+
+    // Other rules pass
+    let output = beholder(&[
+        "validate",
+        "--database-url",
+        &database.url,
+        "--rule",
+        "volume",
+        "--rule",
+        "monotonic",
+    ]);
+    assert!(output.status.success());
+
+    let output = beholder(&["validate", "--database-url", &database.url, "--quarantine"]);
+    assert!(output.status.success());
+    assert_eq!(database.count("binance_klines"), 4);
+    assert_eq!(database.count("binance_klines_quarantine"), 1);
+    assert!(beholder(&["validate", "--database-url", &database.url])
+        .status
+        .success());
+
+    // Inline checks keep the kline out of binance_klines
+    assert!(snapshot_klines(&mock, &database, true));
+    assert_eq!(database.count("binance_klines"), 4);
+    assert_eq!(database.count("binance_klines_quarantine"), 1);
+}
@@ -0,0 +1,118 @@
+mod common;
+
+use common::{beholder, MockBinance, TestDatabase};
+use diesel::prelude::*;
+
+fn enqueue(database: &TestDatabase, arguments: &[&str]) {
+    let mut command = vec![
+        "enqueue",
+        "--database-url",
+        &database.url,
+        "--config",
+        "tests/assets/queue.toml",
+    ];
+    command.extend_from_slice(arguments);
+    assert!(beholder(&command).status.success());
+}
+
+fn work(mock: &MockBinance, database: &TestDatabase) -> bool {
+    beholder(&[
+        "worker",
+        "--database-url",
+        &database.url,
+        "--api-base-url",
+        &mock.api_url,
+        "--retries",
+        "0",
+        "--retry-delay",
+        "1s",
+        "--poll-interval",
+        "1s",
+        "--exit-when-done",
+    ])
+    .status
+    .success()
+}
+
+fn tasks(database: &TestDatabase, condition: &str) -> i64 {
+    database.count(&format!("beholder_tasks WHERE {}", condition))
+}
+
+#[test]
+fn workers_run_queued_tasks() {
+    let Some(database) = TestDatabase::create("worker") else {
+        return;
+    };
+    assert!(
+        beholder(&["migrate", "--database-url", &database.url, "up"])
+            .status
+            .success()
+    );
+    let mock = MockBinance::start();
+    mock.serve_klines("tests/assets/binance/klines_BTCUSDT_1m.json");
+
+    // Tasks without attempts would never run
+    assert!(!beholder(&[
+        "enqueue",
+        "--database-url",
+        &database.url,
+        "--config",
+        "tests/assets/queue.toml",
+        "--max-attempts",
+        "0",
+    ])
+    .status
+    .success());
+    assert_eq!(database.count("beholder_tasks"), 0);
+
+    // Five minutes in chunks of two, enqueuing again adds nothing
+    enqueue(&database, &["--job", "backfill", "--chunk", "2m"]);
+    enqueue(&database, &["--job", "backfill", "--chunk", "2m"]);
+    assert_eq!(database.count("beholder_tasks"), 3);
+
+    // The first attempt fails and is retried
+    mock.fail_next(1);
+    assert!(work(&mock, &database));
+    assert_eq!(database.count("binance_klines"), 5);
+    assert_eq!(tasks(&database, "status = 'succeeded'"), 3);
+    assert_eq!(tasks(&database, "attempts = 2"), 1);
+    let runs = database.runs();
+    assert_eq!(runs.len(), 1);
+    assert_eq!(runs[0].command, "worker");
+    assert_eq!(runs[0].rows_written, 5);
+
+    // Without attempts left the task fails for good
+    enqueue(
+        &database,
+        &[
+            "--job",
+            "single-attempt",
+            "--chunk",
+            "5m",
+            "--max-attempts",
+            "1",
+        ],
+    );
+    mock.fail_next(1);
+    assert!(!work(&mock, &database));
+    assert_eq!(tasks(&database, "status = 'failed'"), 1);
+    assert_eq!(tasks(&database, "error IS NOT NULL"), 1);
+
+    // A worker died on the last attempt, the task fails once its lease expired
+    enqueue(
+        &database,
+        &["--job", "abandoned", "--chunk", "5m", "--max-attempts", "1"],
+    );
+    diesel::sql_query(
+        "UPDATE beholder_tasks SET status = 'running', attempts = 1, \
+         available_at = '2000-01-01 00:00:00' WHERE job = 'abandoned'",
+    )
+    .execute(&mut database.connect())
+    .unwrap();
+    assert!(work(&mock, &database));
+    assert_eq!(
+        tasks(&database, "job = 'abandoned' AND status = 'failed'"),
+        1
+    );
+    assert_eq!(tasks(&database, "status = 'failed'"), 2);
+}